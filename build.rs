@@ -3,7 +3,9 @@ use std::path::PathBuf;
 
 fn main() {
     let target = env::var("TARGET").unwrap();
-    if target.contains("pc-windows") {
+    // only the SDL frontend needs the SDL2 libraries
+    let sdl_frontend = env::var("CARGO_FEATURE_SDL_FRONTEND").is_ok();
+    if sdl_frontend && target.contains("pc-windows") {
         let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
         let mut lib_dir = manifest_dir.clone();
         let mut dll_dir = manifest_dir.clone();
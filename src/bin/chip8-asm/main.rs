@@ -0,0 +1,76 @@
+// Assembles a classic or Octo source file into a ROM, optionally writing a
+// listing next to it
+extern crate chip8;
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+
+fn usage() -> &'static str {
+    "usage: chip8-asm [--octo] [-o ROM] [--listing FILE] SOURCE\n\
+     \x20 --octo          Octo syntax instead of the classic mnemonics, the default for .8o files\n\
+     \x20 -o ROM          where to write the ROM, SOURCE with a .ch8 extension by default\n\
+     \x20 --listing FILE  also write the address, bytes and source line of everything emitted"
+}
+
+fn write(path: &str, bytes: &[u8]) {
+    if let Err(err) = File::create(path).and_then(|mut f| f.write_all(bytes)) {
+        eprintln!("couldn't write {}: {}", path, err);
+        process::exit(2);
+    }
+}
+
+fn main() {
+    let mut octo = false;
+    let mut output = None;
+    let mut listing = None;
+    let mut source = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next().unwrap_or_else(|| {
+                eprintln!("{} needs a value\n{}", name, usage());
+                process::exit(2);
+            })
+        };
+        match arg.as_str() {
+            "--octo" => octo = true,
+            "-o" => output = Some(value("-o")),
+            "--listing" => listing = Some(value("--listing")),
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option: {}\n{}", arg, usage());
+                process::exit(2);
+            }
+            _ => source = Some(arg),
+        }
+    }
+    let source = source.unwrap_or_else(|| {
+        eprintln!("PLEASE GIVE ME SOMETHING TO ASSEMBLE!!\n{}", usage());
+        process::exit(2);
+    });
+    let output = output.unwrap_or_else(|| {
+        Path::new(&source)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let octo = octo
+        || Path::new(&source)
+            .extension()
+            .is_some_and(|ext| ext == "8o");
+    let assembly = if octo {
+        chip8::assemble_octo_file(&source)
+    } else {
+        chip8::assemble_file(&source)
+    };
+    let assembly = assembly.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+    write(&output, &assembly.rom);
+    if let Some(listing) = listing {
+        write(&listing, assembly.listing_text().as_bytes());
+    }
+}
@@ -0,0 +1,50 @@
+// Prints a ROM as assembly, code and data told apart by tracing the control flow
+extern crate chip8;
+
+use std::fs::File;
+use std::io::Read;
+use std::process;
+
+fn usage() -> &'static str {
+    "usage: chip8-disasm [--octo] [--linear] ROM\n\
+     \x20 --octo    Octo syntax instead of the classic mnemonics\n\
+     \x20 --linear  decode everything as code instead of tracing jumps and calls"
+}
+
+fn main() {
+    let mut octo = false;
+    let mut linear = false;
+    let mut rom_path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--octo" => octo = true,
+            "--linear" => linear = true,
+            _ if arg.starts_with("--") => {
+                eprintln!("unknown option: {}\n{}", arg, usage());
+                process::exit(2);
+            }
+            _ => rom_path = Some(arg),
+        }
+    }
+    let rom_path = rom_path.unwrap_or_else(|| {
+        eprintln!("PLEASE GIVE ME A ROM!!\n{}", usage());
+        process::exit(2);
+    });
+
+    let mut rom = Vec::new();
+    if let Err(err) = File::open(&rom_path).and_then(|mut f| f.read_to_end(&mut rom)) {
+        eprintln!("couldn't read {}: {}", rom_path, err);
+        process::exit(2);
+    }
+
+    let disassembly = if linear {
+        chip8::Disassembly::linear(&rom)
+    } else {
+        chip8::Disassembly::new(&rom)
+    };
+    if octo {
+        print!("{}", disassembly.octo());
+    } else {
+        print!("{}", disassembly.classic());
+    }
+}
@@ -0,0 +1,91 @@
+// Loads a ROM and waits for gdb (or anything else speaking the remote serial
+// protocol) on a local TCP port:
+//
+//   chip8-gdb --port 1234 game.ch8
+//   (gdb) target remote localhost:1234
+extern crate chip8;
+
+use std::fs::File;
+use std::io::Read;
+use std::net::TcpListener;
+use std::process;
+
+fn usage() -> String {
+    let load_store: Vec<&str> = chip8::LoadStore::NAMES
+        .iter()
+        .map(|&(name, _)| name)
+        .collect();
+    format!(
+        "usage: chip8-gdb [OPTIONS] ROM\n\
+         \x20 --port N                  TCP port on localhost, default 1234\n\
+         \x20 --quirks PRESET[,+QUIRK|,-QUIRK...]  quirks, default chip48\n\
+         \x20 --seed N                  random seed, default 0\n\
+         \x20 --cycles-per-frame N      instructions per frame, default 10\n\
+         quirks: {}, load_store={}",
+        chip8::Quirks::NAMES.join(", "),
+        load_store.join("|")
+    )
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n{}", message, usage());
+    process::exit(2);
+}
+
+fn number(option: &str, value: Option<String>) -> u64 {
+    let value = value.unwrap_or_else(|| fail(&format!("{} needs a value", option)));
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.unwrap_or_else(|_| fail(&format!("invalid number: {}", value)))
+}
+
+fn main() {
+    let mut port = 1234;
+    let mut quirks = chip8::Quirks::CHIP_48;
+    let mut seed = 0;
+    let mut cycles_per_frame = 10;
+    let mut rom_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => port = number(&arg, args.next()) as u16,
+            "--seed" => seed = number(&arg, args.next()),
+            "--cycles-per-frame" => cycles_per_frame = number(&arg, args.next()) as u32,
+            "--quirks" => {
+                let value = args
+                    .next()
+                    .unwrap_or_else(|| fail("--quirks needs a value"));
+                quirks = chip8::Quirks::parse(&value).unwrap_or_else(|name| {
+                    fail(&format!("unknown quirks preset or quirk: {}", name))
+                });
+            }
+            _ if arg.starts_with("--") => fail(&format!("unknown option: {}", arg)),
+            _ => rom_path = Some(arg),
+        }
+    }
+    let rom_path = rom_path.unwrap_or_else(|| fail("PLEASE GIVE ME A ROM!!"));
+
+    let mut rom = Vec::new();
+    if let Err(err) = File::open(&rom_path).and_then(|mut f| f.read_to_end(&mut rom)) {
+        eprintln!("couldn't read {}: {}", rom_path, err);
+        process::exit(2);
+    }
+    let mut chip = chip8::with_random(quirks, chip8::XorShift::new(seed));
+    if let Err(err) = chip.load_rom(&rom) {
+        eprintln!("{}: {}", rom_path, err);
+        process::exit(2);
+    }
+
+    let result = TcpListener::bind(("127.0.0.1", port)).and_then(|listener| {
+        eprintln!("waiting for gdb on {}", listener.local_addr()?);
+        let (stream, client) = listener.accept()?;
+        eprintln!("{} connected", client);
+        chip8::serve_gdb(&mut chip, stream, cycles_per_frame)
+    });
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,339 @@
+// Runs a ROM without a window, for CI and batch testing
+//
+// The machine runs at a fixed speed in 60hz frames, scripted key
+// presses are applied at the start of their frame, and at the end the screen
+// is dumped as PNG and/or ASCII together with a JSON summary of the registers
+extern crate chip8;
+
+mod png;
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::process;
+
+struct KeyEvent {
+    frame: u32,
+    key: u8,
+    down: bool,
+}
+
+struct Options {
+    rom_path: String,
+    quirks: chip8::Quirks,
+    seed: u64,
+    frames: u32,
+    speed: chip8::Speed,
+    until_pc: Option<u16>,
+    until_loop: bool,
+    keys: Vec<KeyEvent>,
+    png: Option<String>,
+    scale: usize,
+    ascii: Option<String>,
+    json: Option<String>,
+    trace: Option<String>,
+}
+
+// Why the run ended
+enum Stop {
+    Frames,
+    Pc,
+    Loop,
+    Exited,
+    Error(chip8::CpuError),
+}
+
+// Same colors as the SDL frontend
+const PALETTE: [(u8, u8, u8); 4] = [(59, 89, 152), (247, 247, 247), (255, 170, 40), (20, 30, 60)];
+
+// Pixel characters for the ASCII dump, indexed by the lit bitplanes
+const ASCII: [char; 4] = ['.', '#', '+', '*'];
+
+fn usage() -> String {
+    let load_store: Vec<&str> = chip8::LoadStore::NAMES
+        .iter()
+        .map(|&(name, _)| name)
+        .collect();
+    format!(
+        "usage: chip8-headless [OPTIONS] ROM\n\
+         \x20 --quirks PRESET[,+QUIRK|,-QUIRK...]  quirks, default chip48\n\
+         \x20 --seed N                  random seed, default 0\n\
+         \x20 --frames N                frames to run, default 600 (10 seconds)\n\
+         \x20 --speed N|Nhz|vip         instructions per frame, a second or as fast as a COSMAC VIP, default 10\n\
+         \x20 --cycles-per-frame N      same as --speed N\n\
+         \x20 --until-pc ADDR           stop before running the instruction at ADDR\n\
+         \x20 --until-loop              stop when a jump to itself is reached\n\
+         \x20 --keys SCRIPT|@FILE       key events like \"30:+5 40:-5\" (frame:+key/-key, hex keys)\n\
+         \x20 --png FILE                write the screen as PNG\n\
+         \x20 --scale N                 PNG pixels per CHIP-8 pixel, default 1\n\
+         \x20 --ascii FILE|-            write the screen as text\n\
+         \x20 --json FILE|-             write a JSON summary of the registers\n\
+         \x20 --trace FILE              record every instruction, for chip8-tracediff\n\
+         quirks: {}, load_store={}",
+        chip8::Quirks::NAMES.join(", "),
+        load_store.join("|")
+    )
+}
+
+// Decimal or 0x prefixed hex
+fn parse_number(value: &str) -> Result<u64, String> {
+    let parsed = if let Some(hex) = value.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16)
+    } else {
+        value.parse()
+    };
+    parsed.map_err(|_| format!("invalid number: {}", value))
+}
+
+// Whitespace or comma separated FRAME:+KEY / FRAME:-KEY events, keys in hex
+fn parse_keys(script: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut events = Vec::new();
+    for event in script.split(|c: char| c == ',' || c.is_whitespace()) {
+        if event.is_empty() {
+            continue;
+        }
+        let invalid = || format!("invalid key event: {}", event);
+        let mut parts = event.splitn(2, ':');
+        let frame = parts.next().unwrap_or("");
+        let key = parts.next().ok_or_else(invalid)?;
+        let frame = parse_number(frame).map_err(|_| invalid())? as u32;
+        let down = match key.chars().next() {
+            Some('+') => true,
+            Some('-') => false,
+            _ => return Err(invalid()),
+        };
+        let key = u8::from_str_radix(&key[1..], 16).map_err(|_| invalid())?;
+        if key > 0xF {
+            return Err(invalid());
+        }
+        events.push(KeyEvent { frame, key, down });
+    }
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut buf))
+        .map_err(|err| format!("couldn't read {}: {}", path, err))?;
+    Ok(buf)
+}
+
+// "-" is stdout
+fn write_file(path: &str, contents: &[u8]) -> Result<(), String> {
+    let result = if path == "-" {
+        std::io::stdout().write_all(contents)
+    } else {
+        File::create(path).and_then(|mut f| f.write_all(contents))
+    };
+    result.map_err(|err| format!("couldn't write {}: {}", path, err))
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: String::new(),
+        quirks: chip8::Quirks::CHIP_48,
+        seed: 0,
+        frames: 600,
+        speed: chip8::Speed::PerFrame(10),
+        until_pc: None,
+        until_loop: false,
+        keys: Vec::new(),
+        png: None,
+        scale: 1,
+        ascii: None,
+        json: None,
+        trace: None,
+    };
+    let mut rom_path = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--until-loop" {
+            options.until_loop = true;
+            continue;
+        }
+        if !arg.starts_with("--") {
+            rom_path = Some(arg);
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--quirks" => {
+                options.quirks = chip8::Quirks::parse(&value)
+                    .map_err(|name| format!("unknown quirks preset or quirk: {}", name))?
+            }
+            "--seed" => options.seed = parse_number(&value)?,
+            "--frames" => options.frames = parse_number(&value)? as u32,
+            "--speed" => {
+                options.speed = chip8::Speed::parse(&value)
+                    .ok_or_else(|| format!("invalid speed: {}", value))?
+            }
+            "--cycles-per-frame" => {
+                options.speed = chip8::Speed::PerFrame(parse_number(&value)? as u32)
+            }
+            "--until-pc" => options.until_pc = Some(parse_number(&value)? as u16),
+            "--keys" => {
+                options.keys = if let Some(path) = value.strip_prefix('@') {
+                    parse_keys(&String::from_utf8_lossy(&read_file(path)?))?
+                } else {
+                    parse_keys(&value)?
+                }
+            }
+            "--png" => options.png = Some(value),
+            "--scale" => options.scale = parse_number(&value)?.max(1) as usize,
+            "--ascii" => options.ascii = Some(value),
+            "--json" => options.json = Some(value),
+            "--trace" => options.trace = Some(value),
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+
+    options.rom_path = rom_path.ok_or("PLEASE GIVE ME A ROM!!")?;
+    Ok(options)
+}
+
+// Returns why it stopped and how many frames and instructions ran
+fn run(chip: &mut chip8::Cpu, options: &Options) -> (Stop, u32, u64) {
+    let mut keys = options.keys.iter().peekable();
+    let mut scheduler = chip8::Scheduler::new(options.speed);
+    let mut instructions = 0;
+    for frame in 0..options.frames {
+        while let Some(event) = keys.next_if(|event| event.frame <= frame) {
+            if event.down {
+                chip.key_down(event.key);
+            } else {
+                chip.key_up(event.key);
+            }
+        }
+        scheduler.start_frame(chip.cycles());
+        while scheduler.more(chip.cycles()) {
+            let pc = chip.pc();
+            if options.until_pc == Some(pc) {
+                return (Stop::Pc, frame, instructions);
+            }
+            let outcome = chip.cpu_tick();
+            // waiting for a key or the vblank isn't running anything
+            if outcome == Ok(chip8::StepOutcome::Executed) {
+                instructions += 1;
+            }
+            match outcome {
+                Ok(chip8::StepOutcome::Executed) if options.until_loop && chip.pc() == pc => {
+                    return (Stop::Loop, frame, instructions)
+                }
+                Ok(chip8::StepOutcome::Exited) => return (Stop::Exited, frame, instructions),
+                Ok(chip8::StepOutcome::WaitingForVblank) => break,
+                Ok(_) => {}
+                Err(err) => return (Stop::Error(err), frame, instructions),
+            }
+        }
+        chip.timers_tick();
+    }
+    (Stop::Frames, options.frames, instructions)
+}
+
+fn ascii(chip: &chip8::Cpu) -> String {
+    let mut out = String::new();
+    for row in chip.gfx[..chip.width() * chip.height()].chunks(chip.width()) {
+        out.extend(row.iter().map(|&pixel| ASCII[pixel as usize & 0x3]));
+        out.push('\n');
+    }
+    out
+}
+
+fn json(chip: &chip8::Cpu, stop: &Stop, frames: u32, instructions: u64) -> String {
+    let stop = match *stop {
+        Stop::Frames => "\"frames\"".to_string(),
+        Stop::Pc => "\"pc\"".to_string(),
+        Stop::Loop => "\"loop\"".to_string(),
+        Stop::Exited => "\"exited\"".to_string(),
+        Stop::Error(err) => format!("\"error\", \"error\": \"{}\"", err),
+    };
+    let list = |values: Vec<String>| format!("[{}]", values.join(", "));
+    format!(
+        "{{\"stop\": {}, \"frames\": {}, \"instructions\": {}, \"vip_cycles\": {}, \
+         \"pc\": {}, \"i\": {}, \"v\": {}, \"stack\": {}, \"delay_timer\": {}, \
+         \"sound_timer\": {}, \"width\": {}, \"height\": {}}}\n",
+        stop,
+        frames,
+        instructions,
+        chip.cycles(),
+        chip.pc(),
+        chip.i(),
+        list(chip.v().iter().map(|v| v.to_string()).collect()),
+        list(chip.stack().iter().map(|addr| addr.to_string()).collect()),
+        chip.delay_timer,
+        chip.sound_timer,
+        chip.width(),
+        chip.height()
+    )
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n{}", err, usage());
+            process::exit(2);
+        }
+    };
+
+    let rom = read_file(&options.rom_path).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(2);
+    });
+
+    let mut chip = chip8::with_random(options.quirks, chip8::XorShift::new(options.seed));
+    if let Err(err) = chip.load_rom(&rom) {
+        eprintln!("{}: {}", options.rom_path, err);
+        process::exit(2);
+    }
+    if let Some(ref path) = options.trace {
+        match File::create(path) {
+            Ok(file) => chip.start_trace(BufWriter::new(file)),
+            Err(err) => {
+                eprintln!("couldn't write {}: {}", path, err);
+                process::exit(2);
+            }
+        }
+    }
+
+    let (stop, frames, instructions) = run(&mut chip, &options);
+    if let Stop::Error(err) = stop {
+        eprintln!("{}", err);
+    }
+    if let Err(err) = chip.stop_trace() {
+        eprintln!("couldn't write the trace: {}", err);
+        process::exit(2);
+    }
+
+    let mut outputs = Vec::new();
+    if let Some(ref path) = options.png {
+        let image = png::encode(
+            &chip.gfx,
+            chip.width(),
+            chip.height(),
+            options.scale,
+            &PALETTE,
+        );
+        outputs.push((path, image));
+    }
+    if let Some(ref path) = options.ascii {
+        outputs.push((path, ascii(&chip).into_bytes()));
+    }
+    if let Some(ref path) = options.json {
+        outputs.push((path, json(&chip, &stop, frames, instructions).into_bytes()));
+    }
+    for (path, contents) in outputs {
+        if let Err(err) = write_file(path, &contents) {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    }
+
+    if let Stop::Error(_) = stop {
+        process::exit(1);
+    }
+}
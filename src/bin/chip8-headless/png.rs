@@ -0,0 +1,88 @@
+// Just enough PNG to save the screen: an indexed color image stored in
+// uncompressed deflate blocks, the screens are tiny so size doesn't matter
+
+// Encode gfx (one palette index per pixel, width x height) scaling every pixel
+// to a scale x scale square
+pub fn encode(
+    gfx: &[u8],
+    width: usize,
+    height: usize,
+    scale: usize,
+    palette: &[(u8, u8, u8); 4],
+) -> Vec<u8> {
+    let mut raw = Vec::new();
+    for row in gfx[..width * height].chunks(width) {
+        // every row starts with its filter type, 0 is none
+        let mut line = vec![0];
+        for &pixel in row {
+            line.extend(std::iter::repeat_n(pixel & 0x3, scale));
+        }
+        for _ in 0..scale {
+            raw.extend_from_slice(&line);
+        }
+    }
+
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&((width * scale) as u32).to_be_bytes());
+    ihdr.extend_from_slice(&((height * scale) as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 3, 0, 0, 0]); // 8 bit, indexed, no interlace
+
+    let plte: Vec<u8> = palette
+        .iter()
+        .flat_map(|&(r, g, b)| vec![r, g, b])
+        .collect();
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &ihdr);
+    chunk(&mut png, b"PLTE", &plte);
+    chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + u32::from(byte)) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
@@ -0,0 +1,110 @@
+// Compares two execution traces, from --trace or Cpu::start_trace, and
+// reports the first instruction where they went different ways
+extern crate chip8;
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::process;
+
+use chip8::TraceRecord;
+
+fn usage() -> &'static str {
+    "usage: chip8-tracediff [--context N] A B\n\
+     \x20 --context N  instructions to show before the difference, default 5"
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
+}
+
+struct Trace {
+    path: String,
+    lines: Lines<BufReader<File>>,
+}
+
+impl Trace {
+    fn open(path: &str) -> Trace {
+        let file = File::open(path)
+            .unwrap_or_else(|err| fail(&format!("couldn't read {}: {}", path, err)));
+        Trace {
+            path: path.to_string(),
+            lines: BufReader::new(file).lines(),
+        }
+    }
+
+    // The next line and its record, None at the end
+    fn next(&mut self) -> Option<(String, TraceRecord)> {
+        let line = self.lines.next()?.unwrap_or_else(|err| {
+            fail(&format!("couldn't read {}: {}", self.path, err));
+        });
+        match TraceRecord::parse(&line) {
+            Some(record) => Some((line, record)),
+            None => fail(&format!("{}: not a trace line: {}", self.path, line)),
+        }
+    }
+}
+
+fn ended(done: &str, going: &str, count: usize, line: &str) -> ! {
+    println!(
+        "{} ends after {} instructions, {} goes on with\n  {}",
+        done, count, going, line
+    );
+    process::exit(1);
+}
+
+fn main() {
+    let mut context = 5;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => {
+                context = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_else(|| fail(&format!("--context needs a number\n{}", usage())));
+            }
+            _ if arg.starts_with("--") => fail(&format!("unknown option: {}\n{}", arg, usage())),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 2 {
+        fail(&format!("PLEASE GIVE ME TWO TRACES!!\n{}", usage()));
+    }
+    let mut a = Trace::open(&paths[0]);
+    let mut b = Trace::open(&paths[1]);
+
+    let mut before = VecDeque::new();
+    let mut count = 0;
+    loop {
+        let (line_a, line_b) = match (a.next(), b.next()) {
+            (None, None) => {
+                println!("the traces are the same, {} instructions", count);
+                return;
+            }
+            (Some((line, _)), None) => ended(&b.path, &a.path, count, &line),
+            (None, Some((line, _))) => ended(&a.path, &b.path, count, &line),
+            (Some(a), Some(b)) => (a, b),
+        };
+        let differences = line_a.1.differences(&line_b.1);
+        if !differences.is_empty() {
+            println!("first difference at instruction {}", count);
+            for line in &before {
+                println!("  {}", line);
+            }
+            println!("{}:\n  {}", a.path, line_a.0);
+            println!("{}:\n  {}", b.path, line_b.0);
+            for difference in differences {
+                println!("  {}", difference);
+            }
+            process::exit(1);
+        }
+        before.push_back(line_a.0);
+        if before.len() > context {
+            before.pop_front();
+        }
+        count += 1;
+    }
+}
@@ -0,0 +1,246 @@
+// Step debugger: an overlay with the machine state that pauses the game loop
+// and runs the program one instruction, call or subroutine at a time
+//
+//   F12        show or hide the overlay, showing it pauses
+//   F9         continue or pause with the overlay open
+//   F11        run one instruction
+//   F10        step over: like F11 but runs a CALL until it returns
+//   Shift+F11  run to return: until the current subroutine returns
+use sdl2::keyboard::{Keycode, Mod, LSHIFTMOD, RSHIFTMOD};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use chip8::{Cpu, Instruction};
+
+use font::{draw_text, CHAR_WIDTH, LINE_HEIGHT};
+
+// Hex keypad layout, as it's drawn
+const KEYPAD: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+// Instructions shown before the one at pc
+const DISASSEMBLY_BEFORE: u16 = 6;
+const DISASSEMBLY_LINES: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Running,
+    Paused,
+    // run one instruction, then pause
+    Step,
+    // pause once pc is back at `pc` with no more than `depth` return
+    // addresses on the stack
+    StepOver { pc: u16, depth: usize },
+    // pause once the stack is shallower than `depth`
+    ToReturn { depth: usize },
+}
+
+pub struct Debugger {
+    pub visible: bool,
+    mode: Mode,
+    // something changed that the overlay should show
+    pub dirty: bool,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            visible: false,
+            mode: Mode::Running,
+            dirty: false,
+        }
+    }
+
+    // While paused the game loop runs no instructions and the timers stop
+    pub fn paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+
+    // Handles a key press, true when it was one of the debugger keys
+    pub fn key_down(&mut self, key: Keycode, keymod: Mod, chip: &Cpu) -> bool {
+        let shift = keymod.intersects(LSHIFTMOD | RSHIFTMOD);
+        match key {
+            Keycode::F12 => {
+                self.visible = !self.visible;
+                self.mode = if self.visible {
+                    Mode::Paused
+                } else {
+                    Mode::Running
+                };
+            }
+            _ if !self.visible => return false,
+            Keycode::F9 => {
+                self.mode = if self.paused() {
+                    Mode::Running
+                } else {
+                    Mode::Paused
+                };
+            }
+            Keycode::F11 if shift => {
+                // at the top level there's nothing to return from
+                let depth = chip.stack().len();
+                if depth > 0 {
+                    self.mode = Mode::ToReturn { depth };
+                }
+            }
+            Keycode::F11 => self.mode = Mode::Step,
+            Keycode::F10 => {
+                self.mode = match word(chip, chip.pc()).and_then(Instruction::decode) {
+                    Some(Instruction::Call { .. }) => Mode::StepOver {
+                        pc: chip.pc().wrapping_add(2),
+                        depth: chip.stack().len(),
+                    },
+                    _ => Mode::Step,
+                };
+            }
+            _ => return false,
+        }
+        self.dirty = true;
+        true
+    }
+
+    // Called after every instruction the game loop runs, pauses when a step
+    // is done
+    pub fn after_tick(&mut self, chip: &Cpu) {
+        let done = match self.mode {
+            Mode::Running | Mode::Paused => false,
+            Mode::Step => true,
+            Mode::StepOver { pc, depth } => chip.pc() == pc && chip.stack().len() <= depth,
+            Mode::ToReturn { depth } => chip.stack().len() < depth,
+        };
+        if done {
+            self.mode = Mode::Paused;
+            self.dirty = true;
+        }
+    }
+
+    // Pauses with the overlay open, for errors the program stopped at
+    pub fn stop(&mut self) {
+        self.visible = true;
+        self.mode = Mode::Paused;
+        self.dirty = true;
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<Window>, chip: &Cpu, text: Color, highlight: Color) {
+        let (width, height) = canvas.output_size().unwrap();
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 190));
+        canvas.fill_rect(Rect::new(0, 0, width, height)).unwrap();
+        canvas.set_blend_mode(BlendMode::None);
+
+        let mut column = Column {
+            x: CHAR_WIDTH,
+            y: LINE_HEIGHT / 2,
+        };
+        let status = if self.paused() { "PAUSED" } else { "RUNNING" };
+        column.line(canvas, status, highlight);
+        column.line(canvas, "F9 run/pause  F11 step  F10 step over", text);
+        column.line(canvas, "Shift+F11 run to return  F12 close", text);
+        column.y += LINE_HEIGHT;
+
+        let v = chip.v();
+        for row in 0..4 {
+            let registers: Vec<String> = (row * 4..row * 4 + 4)
+                .map(|x| format!("V{:X} {:02X}", x, v[x]))
+                .collect();
+            column.line(canvas, &registers.join("  "), text);
+        }
+        column.y += LINE_HEIGHT;
+        let pointers = format!(
+            "PC {:04X}  I {:04X}  SP {}",
+            chip.pc(),
+            chip.i(),
+            chip.stack().len()
+        );
+        column.line(canvas, &pointers, text);
+        let timers = format!("DT {:02X}    ST {:02X}", chip.delay_timer, chip.sound_timer);
+        column.line(canvas, &timers, text);
+        column.y += LINE_HEIGHT;
+
+        // innermost call first
+        column.line(canvas, "STACK", text);
+        let stack: Vec<String> = chip
+            .stack()
+            .iter()
+            .rev()
+            .map(|addr| format!("{:04X}", addr))
+            .collect();
+        if stack.is_empty() {
+            column.line(canvas, "  empty", text);
+        }
+        for chunk in stack.chunks(4) {
+            column.line(canvas, &format!("  {}", chunk.join(" ")), text);
+        }
+        column.y += LINE_HEIGHT;
+
+        column.line(canvas, "KEYS", text);
+        for keys in KEYPAD.iter() {
+            for (n, &key) in keys.iter().enumerate() {
+                let color = if chip.is_key_down(key) {
+                    highlight
+                } else {
+                    text
+                };
+                let x = column.x + (2 + 2 * n as i32) * CHAR_WIDTH;
+                draw_text(canvas, x, column.y, &format!("{:X}", key), color);
+            }
+            column.y += LINE_HEIGHT;
+        }
+
+        // disassembly around pc on the right half
+        let x = width as i32 / 2;
+        let mut addr = chip.pc().saturating_sub(2 * DISASSEMBLY_BEFORE);
+        for n in 0..DISASSEMBLY_LINES {
+            let opcode = match word(chip, addr) {
+                Some(opcode) => opcode,
+                None => break,
+            };
+            let instruction = if opcode == 0xF000 {
+                word(chip, addr.wrapping_add(2))
+                    .and_then(|next| Instruction::decode_pair(opcode, next))
+            } else {
+                Instruction::decode(opcode)
+            };
+            let (mnemonic, size) = match instruction {
+                Some(instruction) => (instruction.to_string(), instruction.size()),
+                None => ("?".to_string(), 2),
+            };
+            let at_pc = addr == chip.pc();
+            let marker = if at_pc { '>' } else { ' ' };
+            let y = LINE_HEIGHT / 2 + n as i32 * LINE_HEIGHT;
+            let color = if at_pc { highlight } else { text };
+            draw_text(
+                canvas,
+                x,
+                y,
+                &format!("{}{:04X} {:04X} {}", marker, addr, opcode, mnemonic),
+                color,
+            );
+            addr = addr.wrapping_add(size);
+        }
+    }
+}
+
+// Where the text of the left half goes next
+struct Column {
+    x: i32,
+    y: i32,
+}
+
+impl Column {
+    fn line(&mut self, canvas: &mut Canvas<Window>, text: &str, color: Color) {
+        draw_text(canvas, self.x, self.y, text, color);
+        self.y += LINE_HEIGHT;
+    }
+}
+
+fn word(chip: &Cpu, addr: u16) -> Option<u16> {
+    let addr = addr as usize;
+    let bytes = chip.memory().get(addr..addr + 2)?;
+    Some(u16::from(bytes[0]) << 8 | u16::from(bytes[1]))
+}
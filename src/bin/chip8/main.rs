@@ -0,0 +1,526 @@
+extern crate env_logger;
+#[macro_use]
+extern crate log;
+
+extern crate sdl2;
+
+extern crate chip8;
+
+mod debugger;
+mod font;
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::collections::HashMap;
+use std::iter::FromIterator;
+use std::process;
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::audio::{AudioCallback, AudioSpecDesired};
+
+// Plays the XO-CHIP audio pattern buffer when the program loaded one and the
+// classic square wave beep otherwise
+struct SoundWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+    channels: usize,
+    freq: f32,
+    pattern: Option<[u8; 16]>,
+    pattern_rate: f32, // bits per second
+    pattern_pos: f32,  // bit of the pattern being played, 0 to 128
+}
+
+impl SoundWave {
+    fn beep_sample(&mut self) -> f32 {
+        let sample = if self.phase <= 0.55 {
+            self.volume
+        } else {
+            -self.volume
+        };
+        self.phase = (self.phase + self.phase_inc) % 1.0;
+        sample
+    }
+
+    // The pattern is 128 bits played MSB first, each one either high or low
+    fn pattern_sample(&mut self, pattern: &[u8; 16]) -> f32 {
+        let bit = self.pattern_pos as usize;
+        let high = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+        self.pattern_pos = (self.pattern_pos + self.pattern_rate / self.freq) % 128.0;
+        if high {
+            self.volume
+        } else {
+            -self.volume
+        }
+    }
+}
+
+impl AudioCallback for SoundWave {
+    type Channel = f32;
+    fn callback(&mut self, out: &mut [f32]) {
+        for frame in out.chunks_mut(self.channels) {
+            let sample = match self.pattern {
+                Some(pattern) => self.pattern_sample(&pattern),
+                None => self.beep_sample(),
+            };
+            for x in frame.iter_mut() {
+                *x = sample;
+            }
+        }
+    }
+}
+
+struct Options {
+    rom_path: String,
+    quirks: chip8::Quirks,
+    palette: [Color; 4],
+    seed: u64,
+    rewind_seconds: u32,
+    speed: chip8::Speed,
+    trace: Option<String>,
+}
+
+// Colors for the four combinations of the two XO-CHIP bitplanes: none lit,
+// only the first (the one plain CHIP-8 programs draw on), only the second, both
+// Facebook colors, lol
+const DEFAULT_PALETTE: [(u8, u8, u8); 4] =
+    [(59, 89, 152), (247, 247, 247), (255, 170, 40), (20, 30, 60)];
+
+fn usage() -> String {
+    let load_store: Vec<&str> = chip8::LoadStore::NAMES
+        .iter()
+        .map(|&(name, _)| name)
+        .collect();
+    let presets: Vec<&str> = chip8::Quirks::PRESETS
+        .iter()
+        .map(|&(name, _)| name)
+        .collect();
+    format!(
+        "usage: chip8 [--quirks PRESET[,+QUIRK|,-QUIRK...]] [--palette RRGGBB,RRGGBB,RRGGBB,RRGGBB] [--seed N] [--rewind SECONDS] [--speed N|Nhz|vip] [--trace FILE] ROM\n\
+         --speed is N instructions per frame (default 10), e.g. 700hz or vip for COSMAC VIP timing, +/- change it while running\n\
+         hold Backspace to rewind up to SECONDS (default 10, 0 turns it off)\n\
+         --trace records every instruction to FILE, for chip8-tracediff\n\
+         F1-F4 save the state to slots 1-4, F5-F8 load them\n\
+         F12 opens the debugger: F9 run/pause, F11 step, F10 step over, Shift+F11 run to return\n\
+         presets: {} (default chip48)\n\
+         quirks: {}, load_store={}",
+        presets.join(", "),
+        chip8::Quirks::NAMES.join(", "),
+        load_store.join("|")
+    )
+}
+
+fn set_title(canvas: &mut Canvas<Window>, speed: chip8::Speed) {
+    let title = format!("chip8 interpreter - {}", speed);
+    canvas.window_mut().set_title(&title).unwrap();
+}
+
+// Quick-save slots live next to the ROM, game.ch8 -> game.ch8.1.state
+fn slot_path(rom_path: &str, slot: u8) -> String {
+    format!("{}.{}.state", rom_path, slot)
+}
+
+fn save_slot(chip: &chip8::Cpu, rom_path: &str, slot: u8) {
+    let path = slot_path(rom_path, slot);
+    match File::create(&path).and_then(|mut f| f.write_all(&chip.save_state())) {
+        Ok(()) => info!("saved slot {} to {}", slot, path),
+        Err(err) => error!("couldn't save slot {} to {}: {}", slot, path, err),
+    }
+}
+
+fn load_slot(chip: &mut chip8::Cpu, rom_path: &str, slot: u8) {
+    let path = slot_path(rom_path, slot);
+    let mut state = Vec::new();
+    if let Err(err) = File::open(&path).and_then(|mut f| f.read_to_end(&mut state)) {
+        error!("couldn't read slot {} from {}: {}", slot, path, err);
+        return;
+    }
+    match chip.load_state(&state) {
+        Ok(()) => info!("loaded slot {} from {}", slot, path),
+        Err(err) => error!("couldn't load slot {} from {}: {}", slot, path, err),
+    }
+}
+
+// Four comma separated RRGGBB hex colors
+fn parse_palette(spec: &str) -> Result<[Color; 4], String> {
+    let colors = spec
+        .split(',')
+        .map(|hex| {
+            let hex = hex.trim_start_matches('#');
+            match u32::from_str_radix(hex, 16) {
+                Ok(rgb) if hex.len() == 6 => {
+                    Ok(Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+                }
+                _ => Err(format!("invalid color: {}", hex)),
+            }
+        })
+        .collect::<Result<Vec<Color>, String>>()?;
+    if colors.len() != 4 {
+        return Err("the palette needs exactly 4 colors".to_string());
+    }
+    Ok([colors[0], colors[1], colors[2], colors[3]])
+}
+
+// Decimal or 0x prefixed hex
+fn parse_number(value: &str) -> Result<u64, String> {
+    let parsed = if let Some(hex) = value.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16)
+    } else {
+        value.parse()
+    };
+    parsed.map_err(|_| format!("invalid number: {}", value))
+}
+
+// Different every run unless --seed is given
+fn time_seed() -> u64 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_secs() ^ u64::from(now.subsec_nanos())
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut quirks = chip8::Quirks::CHIP_48;
+    let mut palette = DEFAULT_PALETTE.map(|(r, g, b)| Color::RGB(r, g, b));
+    let mut seed = None;
+    let mut rewind_seconds = 10;
+    let mut speed = chip8::Speed::PerFrame(10);
+    let mut trace = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let spec = args.next().ok_or("--quirks needs a value")?;
+                quirks = chip8::Quirks::parse(&spec)
+                    .map_err(|name| format!("unknown quirks preset or quirk: {}", name))?;
+            }
+            "--palette" => {
+                let spec = args.next().ok_or("--palette needs a value")?;
+                palette = parse_palette(&spec)?;
+            }
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a value")?;
+                seed = Some(parse_number(&value)?);
+            }
+            "--rewind" => {
+                let value = args.next().ok_or("--rewind needs a value")?;
+                rewind_seconds = value
+                    .parse()
+                    .map_err(|_| format!("invalid number of seconds: {}", value))?;
+            }
+            "--speed" => {
+                let value = args.next().ok_or("--speed needs a value")?;
+                speed = chip8::Speed::parse(&value)
+                    .ok_or_else(|| format!("invalid speed: {}", value))?;
+            }
+            "--trace" => trace = Some(args.next().ok_or("--trace needs a value")?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    match rom_path {
+        Some(rom_path) => Ok(Options {
+            rom_path,
+            quirks,
+            palette,
+            seed: seed.unwrap_or_else(time_seed),
+            rewind_seconds,
+            speed,
+            trace,
+        }),
+        None => Err("PLEASE GIVE ME A ROM!!".to_string()),
+    }
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(err) => {
+            println!("{}\n{}", err, usage());
+            return;
+        }
+    };
+
+    let mut f = File::open(&options.rom_path).unwrap();
+
+    let mut buf = Vec::new();
+
+    f.read_to_end(&mut buf).unwrap();
+
+    env_logger::init();
+
+    // run again with --seed to get the same random numbers
+    info!("random seed {}", options.seed);
+    let random = chip8::XorShift::new(options.seed);
+    let mut chip = chip8::with_random(options.quirks, random);
+
+    if let Err(err) = chip.load_rom(&buf) {
+        println!("{}: {}", options.rom_path, err);
+        return;
+    }
+    if let Some(ref path) = options.trace {
+        match File::create(path) {
+            Ok(file) => chip.start_trace(BufWriter::new(file)),
+            Err(err) => {
+                println!("couldn't write {}: {}", path, err);
+                process::exit(1);
+            }
+        }
+    }
+
+    // big enough for whole blocks in both the 64x32 and the 128x64 modes
+    let window_width = 128 * 8;
+    let window_height = 64 * 8;
+
+    let palette = options.palette;
+    let background_color = palette[0];
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window("chip8 interpreter", window_width, window_height)
+        .position_centered()
+        .build()
+        .unwrap();
+
+    let audio_subsystem = sdl_context.audio().unwrap();
+    let desired_spec = AudioSpecDesired {
+        freq: Some(44100),
+        channels: Some(2),
+        samples: None,
+    };
+    let mut device = audio_subsystem
+        .open_playback(None, &desired_spec, |spec| SoundWave {
+            phase_inc: 120.0 / spec.freq as f32,
+            phase: 0.5,
+            volume: 0.10,
+            channels: spec.channels as usize,
+            freq: spec.freq as f32,
+            pattern: None,
+            pattern_rate: 0.0,
+            pattern_pos: 0.0,
+        })
+        .unwrap();
+
+    let mut canvas = window.into_canvas().build().unwrap();
+
+    canvas.set_draw_color(background_color);
+    canvas.clear();
+
+    // 60 frames a second, each one runs its instructions and ticks the timers
+    let mut scheduler = chip8::Scheduler::new(options.speed);
+    let mut past = std::time::Instant::now();
+    set_title(&mut canvas, scheduler.speed);
+
+    let keys: HashMap<Keycode, u8> = HashMap::from_iter(vec![
+        (Keycode::Num1, 0x1),
+        (Keycode::Num2, 0x2),
+        (Keycode::Num3, 0x3),
+        (Keycode::Num4, 0xC),
+        (Keycode::Q, 0x4),
+        (Keycode::W, 0x5),
+        (Keycode::E, 0x6),
+        (Keycode::R, 0xD),
+        (Keycode::A, 0x7),
+        (Keycode::S, 0x8),
+        (Keycode::D, 0x9),
+        (Keycode::F, 0xE),
+        (Keycode::Z, 0xA),
+        (Keycode::X, 0x0),
+        (Keycode::C, 0xB),
+        (Keycode::V, 0xF),
+    ]);
+
+    // F1-F4 quick-save to slots 1-4, F5-F8 quick-load them back
+    let save_keys: HashMap<Keycode, u8> = HashMap::from_iter(vec![
+        (Keycode::F1, 1),
+        (Keycode::F2, 2),
+        (Keycode::F3, 3),
+        (Keycode::F4, 4),
+    ]);
+    let load_keys: HashMap<Keycode, u8> = HashMap::from_iter(vec![
+        (Keycode::F5, 1),
+        (Keycode::F6, 2),
+        (Keycode::F7, 3),
+        (Keycode::F8, 4),
+    ]);
+
+    // one frame of history per timers tick, played backwards while Backspace is held
+    let mut rewind = chip8::Rewind::with_seconds(options.rewind_seconds);
+    let mut rewinding = false;
+
+    let mut debugger = debugger::Debugger::new();
+
+    let mut playing_pattern = None;
+    let mut playing_rate = 0.0;
+
+    'game_loop: loop {
+        for event in sdl_context.event_pump().unwrap().poll_iter() {
+            match event {
+                Event::KeyDown {
+                    keycode: Some(key),
+                    keymod,
+                    ..
+                } => {
+                    if debugger.key_down(key, keymod, &chip) {
+                        continue;
+                    }
+                    if key == Keycode::Escape {
+                        break 'game_loop;
+                    }
+                    if key == Keycode::Backspace {
+                        rewinding = true;
+                    }
+                    let speed = match key {
+                        Keycode::Equals | Keycode::KpPlus => scheduler.speed.faster(),
+                        Keycode::Minus | Keycode::KpMinus => scheduler.speed.slower(),
+                        _ => scheduler.speed,
+                    };
+                    if speed != scheduler.speed {
+                        info!("{}", speed);
+                        scheduler.speed = speed;
+                        set_title(&mut canvas, speed);
+                    }
+                    if keys.contains_key(&key) {
+                        chip.key_down(keys[&key])
+                    }
+                    if let Some(&slot) = save_keys.get(&key) {
+                        save_slot(&chip, &options.rom_path, slot);
+                    }
+                    if let Some(&slot) = load_keys.get(&key) {
+                        load_slot(&mut chip, &options.rom_path, slot);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => {
+                    rewinding = false;
+                    // the states we went through had their own keys held down
+                    let keyboard = sdl_context.event_pump().unwrap();
+                    let keyboard = keyboard.keyboard_state();
+                    for (&keycode, &key) in keys.iter() {
+                        match Scancode::from_keycode(keycode) {
+                            Some(scancode) if keyboard.is_scancode_pressed(scancode) => {
+                                chip.key_down(key)
+                            }
+                            _ => chip.key_up(key),
+                        }
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } if keys.contains_key(&key) => chip.key_up(keys[&key]),
+                Event::Quit { .. } => break 'game_loop,
+                _ => {}
+            }
+        }
+
+        let now = std::time::Instant::now();
+        let frames = scheduler.advance(now - past);
+        past = now;
+
+        for _ in 0..frames {
+            if debugger.visible {
+                debugger.dirty = true;
+            }
+            if rewinding {
+                if let Err(err) = rewind.step_back(&mut chip) {
+                    error!("couldn't rewind: {}", err);
+                }
+                continue;
+            }
+            if debugger.paused() {
+                continue;
+            }
+            scheduler.start_frame(chip.cycles());
+            while scheduler.more(chip.cycles()) {
+                match chip.cpu_tick() {
+                    Ok(chip8::StepOutcome::Exited) => break 'game_loop,
+                    // nothing more runs until a key is pressed or the frame ends
+                    Ok(chip8::StepOutcome::WaitingForKey)
+                    | Ok(chip8::StepOutcome::WaitingForVblank) => break,
+                    Ok(_) => {}
+                    // with the debugger open stop right at the faulty instruction
+                    Err(err) if debugger.visible => {
+                        error!("{}", err);
+                        debugger.stop();
+                    }
+                    Err(err) => {
+                        error!("{}", err);
+                        break 'game_loop;
+                    }
+                }
+                debugger.after_tick(&chip);
+                if debugger.paused() {
+                    break;
+                }
+            }
+            // a step that paused in the middle of the frame doesn't finish it
+            if !debugger.paused() {
+                chip.timers_tick();
+                rewind.push(&chip);
+            }
+        }
+
+        let pattern = chip.audio_pattern().cloned();
+        let pattern_rate = chip.pattern_rate();
+        if pattern != playing_pattern || pattern_rate != playing_rate {
+            let mut wave = device.lock();
+            wave.pattern = pattern;
+            wave.pattern_rate = pattern_rate;
+            playing_pattern = pattern;
+            playing_rate = pattern_rate;
+        }
+
+        if chip.sound_flag && !debugger.paused() {
+            device.resume();
+        } else {
+            device.pause();
+        }
+
+        if chip.draw_flag || debugger.dirty {
+            canvas.set_draw_color(background_color);
+            canvas.clear();
+            let (width, height) = (chip.width(), chip.height());
+            let block_size = window_width / width as u32;
+            for j in 0..height {
+                for i in 0..width {
+                    let pixel = chip.gfx[i + (j * width)];
+                    if pixel != 0 {
+                        canvas.set_draw_color(palette[pixel as usize & 0x3]);
+                        canvas
+                            .fill_rect(Rect::new(
+                                (i * window_width as usize / width) as i32,
+                                (j * window_height as usize / height) as i32,
+                                block_size,
+                                block_size,
+                            ))
+                            .unwrap();
+                    }
+                }
+            }
+            if debugger.visible {
+                debugger.draw(&mut canvas, &chip, palette[1], palette[2]);
+            }
+            canvas.present();
+            // nothing new to show until the next instruction draws
+            chip.draw_flag = false;
+            debugger.dirty = false;
+        }
+
+        if frames == 0 {
+            std::thread::sleep(scheduler.until_next_frame());
+        }
+    }
+    if let Err(err) = chip.stop_trace() {
+        error!("couldn't write the trace: {}", err);
+    }
+}
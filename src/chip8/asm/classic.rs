@@ -0,0 +1,463 @@
+// Classic syntax: Cowgod's mnemonics plus the SUPER-CHIP and XO-CHIP ones
+// the disassembler prints
+//
+//     start:  LD V0, K            ; comments start with a semicolon
+//             LD I, sprite
+//             DRW V1, V2, HEIGHT
+//             JP start
+//     HEIGHT  EQU 2
+//     sprite: DB 0xF0, 0b10010000
+//             DW 0x1234
+//             ORG 0x300
+//             INCLUDE "font.asm"
+//
+// Mnemonics, registers and directives can be in any case, labels and
+// constants can't. A constant can only use the symbols defined above it,
+// labels can be used anywhere
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::super::{AsmError, Instruction};
+
+use super::expr::{self, is_symbol_char};
+use super::{load, read, Assembly, Image, SourceLine, START};
+
+type Symbols = HashMap<String, i64>;
+
+const MNEMONICS: [&str; 32] = [
+    "SYS", "SCD", "SCU", "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE",
+    "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND",
+    "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+// Names an operand can't be a symbol with
+const RESERVED: [&str; 10] = ["I", "[I]", "DT", "ST", "K", "F", "HF", "B", "R", "LONG"];
+
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let mut lines = Vec::new();
+    load("<source>", source, include, 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Assembly, AsmError> {
+    assemble_lines(&read(path.as_ref(), include)?)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI, // [I]
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(String),
+    Expr(String),
+}
+
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    Db(Vec<String>),
+    Dw(Vec<String>),
+    Org(String),
+    Equ {
+        name: String,
+        value: String,
+    },
+}
+
+struct Parsed<'a> {
+    source: &'a SourceLine,
+    label: Option<String>,
+    statement: Option<Statement>,
+}
+
+fn assemble_lines(lines: &[SourceLine]) -> Result<Assembly, AsmError> {
+    let parsed = lines
+        .iter()
+        .map(|source| parse(source).map_err(|message| source.error(message)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // first pass, lay everything out to know where the labels end up
+    let mut symbols = Symbols::new();
+    let mut addrs = Vec::with_capacity(parsed.len());
+    let mut pc = START;
+    for line in parsed.iter() {
+        let error = |message| line.source.error(message);
+        if let Some(Statement::Org(ref value)) = line.statement {
+            pc = org(value, &symbols).map_err(error)?;
+        }
+        if let Some(ref label) = line.label {
+            define(&mut symbols, label, i64::from(pc)).map_err(error)?;
+        }
+        addrs.push(pc);
+        pc += match line.statement {
+            Some(Statement::Instruction {
+                ref mnemonic,
+                ref operands,
+            }) => match (mnemonic.as_str(), operands.get(1)) {
+                ("LD", Some(&Operand::Long(_))) => 4,
+                _ => 2,
+            },
+            Some(Statement::Db(ref values)) => values
+                .iter()
+                .map(|value| string(value).map_or(1, |text| text.len() as u32))
+                .sum(),
+            Some(Statement::Dw(ref values)) => 2 * values.len() as u32,
+            Some(Statement::Equ {
+                ref name,
+                ref value,
+            }) => {
+                let value = eval(value, &symbols).map_err(error)?;
+                define(&mut symbols, name, value).map_err(error)?;
+                0
+            }
+            Some(Statement::Org(_)) | None => 0,
+        };
+    }
+
+    // second pass, every symbol is known now
+    let mut image = Image::default();
+    for (line, &addr) in parsed.iter().zip(addrs.iter()) {
+        let bytes = match line.statement {
+            Some(Statement::Instruction {
+                ref mnemonic,
+                ref operands,
+            }) => {
+                let instruction = instruction(mnemonic, operands, &symbols)
+                    .map_err(|message| line.source.error(message))?;
+                let opcode = instruction.encode();
+                let mut bytes = vec![(opcode >> 8) as u8, opcode as u8];
+                if let Instruction::LdILong { nnnn } = instruction {
+                    bytes.extend_from_slice(&[(nnnn >> 8) as u8, nnnn as u8]);
+                }
+                bytes
+            }
+            Some(Statement::Db(ref values)) => {
+                db(values, &symbols).map_err(|message| line.source.error(message))?
+            }
+            Some(Statement::Dw(ref values)) => {
+                dw(values, &symbols).map_err(|message| line.source.error(message))?
+            }
+            _ => continue,
+        };
+        image.emit(addr, bytes, line.source)?;
+    }
+    Ok(image.finish())
+}
+
+// INCLUDE "file", the other lines are left to parse
+fn include(text: &str) -> Result<Option<String>, String> {
+    let text = strip_comment(text).trim();
+    let (word, rest) = split_word(text);
+    if !word.eq_ignore_ascii_case("INCLUDE") {
+        return Ok(None);
+    }
+    match string(rest.trim()) {
+        Some(name) => Ok(Some(name.to_string())),
+        None => Err("INCLUDE needs a quoted file name".to_string()),
+    }
+}
+
+fn parse(source: &SourceLine) -> Result<Parsed<'_>, String> {
+    let mut text = strip_comment(&source.text).trim();
+
+    let mut label = None;
+    let name_len = text.bytes().take_while(|&c| is_symbol_char(c)).count();
+    if name_len > 0 && text[name_len..].starts_with(':') {
+        label = Some(text[..name_len].to_string());
+        text = text[name_len + 1..].trim();
+    }
+
+    let mut parsed = Parsed {
+        source,
+        label,
+        statement: None,
+    };
+    if text.is_empty() {
+        return Ok(parsed);
+    }
+
+    let (word, rest) = split_word(text);
+    let (second, value) = split_word(rest.trim());
+    if second.eq_ignore_ascii_case("EQU") {
+        parsed.statement = Some(Statement::Equ {
+            name: word.to_string(),
+            value: value.trim().to_string(),
+        });
+        return Ok(parsed);
+    }
+
+    let mnemonic = word.to_ascii_uppercase();
+    let operands = split_operands(rest)?;
+    parsed.statement = Some(match mnemonic.as_str() {
+        "DB" => Statement::Db(operands),
+        "DW" => Statement::Dw(operands),
+        "ORG" if operands.len() == 1 => Statement::Org(operands[0].clone()),
+        "ORG" => return Err("ORG needs one address".to_string()),
+        _ if MNEMONICS.contains(&mnemonic.as_str()) => Statement::Instruction {
+            operands: operands
+                .iter()
+                .map(|operand| operand_kind(operand))
+                .collect(),
+            mnemonic,
+        },
+        _ => return Err(format!("unknown instruction `{}`", word)),
+    });
+    Ok(parsed)
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (n, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..n],
+            _ => {}
+        }
+    }
+    text
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(n) => (&text[..n], &text[n..]),
+        None => (text, ""),
+    }
+}
+
+// Commas inside strings don't count
+fn split_operands(text: &str) -> Result<Vec<String>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut operands = vec![String::new()];
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                operands.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        operands.last_mut().unwrap().push(c);
+    }
+    if quoted {
+        return Err("unterminated string".to_string());
+    }
+    for operand in operands.iter_mut() {
+        *operand = operand.trim().to_string();
+        if operand.is_empty() {
+            return Err("missing operand".to_string());
+        }
+    }
+    Ok(operands)
+}
+
+// The text between quotes, if the operand is a string
+fn string(operand: &str) -> Option<&str> {
+    if operand.len() >= 2 && operand.starts_with('"') && operand.ends_with('"') {
+        Some(&operand[1..operand.len() - 1])
+    } else {
+        None
+    }
+}
+
+fn operand_kind(text: &str) -> Operand {
+    let upper = text.to_ascii_uppercase();
+    match upper.as_str() {
+        "I" => return Operand::I,
+        "[I]" => return Operand::IndirectI,
+        "DT" => return Operand::Dt,
+        "ST" => return Operand::St,
+        "K" => return Operand::K,
+        "F" => return Operand::F,
+        "HF" => return Operand::Hf,
+        "B" => return Operand::B,
+        "R" => return Operand::R,
+        _ => {}
+    }
+    if let Some(x) = register(&upper) {
+        return Operand::V(x);
+    }
+    let (word, rest) = split_word(text);
+    if word.eq_ignore_ascii_case("LONG") {
+        return Operand::Long(rest.trim().to_string());
+    }
+    Operand::Expr(text.to_string())
+}
+
+fn register(text: &str) -> Option<u8> {
+    if text.len() == 2 && text.starts_with('V') {
+        u8::from_str_radix(&text[1..], 16).ok()
+    } else {
+        None
+    }
+}
+
+fn define(symbols: &mut Symbols, name: &str, value: i64) -> Result<(), String> {
+    let upper = name.to_ascii_uppercase();
+    if RESERVED.contains(&upper.as_str()) || register(&upper).is_some() {
+        return Err(format!("`{}` is a register name", name));
+    }
+    if name.as_bytes()[0].is_ascii_digit() || !name.bytes().all(is_symbol_char) {
+        return Err(format!("invalid symbol name `{}`", name));
+    }
+    if symbols.insert(name.to_string(), value).is_some() {
+        return Err(format!("`{}` is already defined", name));
+    }
+    Ok(())
+}
+
+fn eval(text: &str, symbols: &Symbols) -> Result<i64, String> {
+    expr::eval(text, &|name| symbols.get(name).cloned())
+}
+
+// A value in 0..=max
+fn number(text: &str, symbols: &Symbols, max: i64, what: &str) -> Result<u16, String> {
+    let value = eval(text, symbols)?;
+    if !(0..=max).contains(&value) {
+        return Err(format!("{} {} is out of range ({:#X})", what, text, value));
+    }
+    Ok(value as u16)
+}
+
+// Bytes can be negative too, stored in two's complement
+fn byte(text: &str, symbols: &Symbols) -> Result<u8, String> {
+    let value = eval(text, symbols)?;
+    if !(-128..=255).contains(&value) {
+        return Err(format!("byte {} is out of range ({})", text, value));
+    }
+    Ok(value as u8)
+}
+
+fn org(text: &str, symbols: &Symbols) -> Result<u32, String> {
+    let value = eval(text, symbols)?;
+    if !(i64::from(START)..=0xFFFF).contains(&value) {
+        return Err(format!("ORG {:#X} is outside the ROM", value));
+    }
+    Ok(value as u32)
+}
+
+fn db(values: &[String], symbols: &Symbols) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for value in values {
+        match string(value) {
+            Some(text) => bytes.extend_from_slice(text.as_bytes()),
+            None => bytes.push(byte(value, symbols)?),
+        }
+    }
+    Ok(bytes)
+}
+
+fn dw(values: &[String], symbols: &Symbols) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for value in values {
+        let word = number(value, symbols, 0xFFFF, "word")?;
+        bytes.extend_from_slice(&[(word >> 8) as u8, word as u8]);
+    }
+    Ok(bytes)
+}
+
+fn instruction(
+    mnemonic: &str,
+    operands: &[Operand],
+    symbols: &Symbols,
+) -> Result<Instruction, String> {
+    use self::Instruction::*;
+    use self::Operand::{Expr, IndirectI, Long, V};
+
+    let addr = |text: &str| number(text, symbols, 0xFFF, "address");
+    let nibble = |text: &str| number(text, symbols, 0xF, "nibble").map(|n| n as u8);
+    let byte = |text: &str| byte(text, symbols);
+
+    Ok(match (mnemonic, operands) {
+        ("SYS", [Expr(a)]) => Sys { nnn: addr(a)? },
+        ("SCD", [Expr(n)]) => Scd { n: nibble(n)? },
+        ("SCU", [Expr(n)]) => Scu { n: nibble(n)? },
+        ("CLS", []) => Cls,
+        ("RET", []) => Ret,
+        ("SCR", []) => Scr,
+        ("SCL", []) => Scl,
+        ("EXIT", []) => Exit,
+        ("LOW", []) => Low,
+        ("HIGH", []) => High,
+        ("JP", [Expr(a)]) => Jp { nnn: addr(a)? },
+        ("JP", [V(0), Expr(a)]) => JpV0 { nnn: addr(a)? },
+        ("CALL", [Expr(a)]) => Call { nnn: addr(a)? },
+        ("SE", [V(x), V(y)]) => SeReg { x: *x, y: *y },
+        ("SE", [V(x), Expr(nn)]) => SeByte {
+            x: *x,
+            nn: byte(nn)?,
+        },
+        ("SNE", [V(x), V(y)]) => SneReg { x: *x, y: *y },
+        ("SNE", [V(x), Expr(nn)]) => SneByte {
+            x: *x,
+            nn: byte(nn)?,
+        },
+        ("SAVE", [V(x), V(y)]) => SaveRange { x: *x, y: *y },
+        ("LOAD", [V(x), V(y)]) => LoadRange { x: *x, y: *y },
+        ("LD", [V(x), V(y)]) => LdReg { x: *x, y: *y },
+        ("LD", [V(x), Expr(nn)]) => LdByte {
+            x: *x,
+            nn: byte(nn)?,
+        },
+        ("LD", [Operand::I, Expr(a)]) => LdI { nnn: addr(a)? },
+        ("LD", [Operand::I, Long(a)]) => LdILong {
+            nnnn: number(a, symbols, 0xFFFF, "address")?,
+        },
+        ("LD", [V(x), Operand::Dt]) => LdVxDt { x: *x },
+        ("LD", [V(x), Operand::K]) => LdVxK { x: *x },
+        ("LD", [Operand::Dt, V(x)]) => LdDtVx { x: *x },
+        ("LD", [Operand::St, V(x)]) => LdStVx { x: *x },
+        ("LD", [Operand::F, V(x)]) => LdFVx { x: *x },
+        ("LD", [Operand::Hf, V(x)]) => LdHfVx { x: *x },
+        ("LD", [Operand::B, V(x)]) => LdBVx { x: *x },
+        ("LD", [IndirectI, V(x)]) => LdIVx { x: *x },
+        ("LD", [V(x), IndirectI]) => LdVxI { x: *x },
+        ("LD", [Operand::R, V(x)]) => LdRVx { x: *x },
+        ("LD", [V(x), Operand::R]) => LdVxR { x: *x },
+        ("ADD", [V(x), V(y)]) => AddReg { x: *x, y: *y },
+        ("ADD", [V(x), Expr(nn)]) => AddByte {
+            x: *x,
+            nn: byte(nn)?,
+        },
+        ("ADD", [Operand::I, V(x)]) => AddIVx { x: *x },
+        ("OR", [V(x), V(y)]) => Or { x: *x, y: *y },
+        ("AND", [V(x), V(y)]) => And { x: *x, y: *y },
+        ("XOR", [V(x), V(y)]) => Xor { x: *x, y: *y },
+        ("SUB", [V(x), V(y)]) => Sub { x: *x, y: *y },
+        ("SUBN", [V(x), V(y)]) => Subn { x: *x, y: *y },
+        // VY defaults to VX, so the result is the same with either shift quirk
+        ("SHR", [V(x)]) => Shr { x: *x, y: *x },
+        ("SHR", [V(x), V(y)]) => Shr { x: *x, y: *y },
+        ("SHL", [V(x)]) => Shl { x: *x, y: *x },
+        ("SHL", [V(x), V(y)]) => Shl { x: *x, y: *y },
+        ("RND", [V(x), Expr(nn)]) => Rnd {
+            x: *x,
+            nn: byte(nn)?,
+        },
+        ("DRW", [V(x), V(y), Expr(n)]) => Drw {
+            x: *x,
+            y: *y,
+            n: nibble(n)?,
+        },
+        ("SKP", [V(x)]) => Skp { x: *x },
+        ("SKNP", [V(x)]) => Sknp { x: *x },
+        ("PLANE", [Expr(n)]) => Plane { n: nibble(n)? },
+        ("AUDIO", []) => Audio,
+        ("PITCH", [V(x)]) => Pitch { x: *x },
+        _ => return Err(format!("invalid operands for {}", mnemonic)),
+    })
+}
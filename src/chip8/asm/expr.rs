@@ -0,0 +1,135 @@
+// Integer expressions in operands: numbers, symbols, parentheses, unary - and
+// ~ and the binary operators below, loosest first
+//
+// Numbers are decimal, 0x hex or 0b binary, a symbol is whatever the caller's
+// lookup knows about
+
+const LEVELS: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+pub fn eval(text: &str, lookup: &dyn Fn(&str) -> Option<i64>) -> Result<i64, String> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        pos: 0,
+        lookup,
+    };
+    let value = parser.binary(0)?;
+    parser.skip_spaces();
+    if parser.pos != parser.text.len() {
+        return Err(format!(
+            "unexpected `{}` in expression",
+            &text[parser.pos..]
+        ));
+    }
+    Ok(value)
+}
+
+pub fn parse_number(text: &str) -> Option<i64> {
+    let (digits, radix) =
+        if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            (hex, 16)
+        } else if let Some(binary) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+            (binary, 2)
+        } else {
+            (text, 10)
+        };
+    i64::from_str_radix(digits, radix).ok()
+}
+
+pub fn is_symbol_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'.'
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    lookup: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_spaces(&mut self) {
+        while self.pos < self.text.len() && self.text[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        self.skip_spaces();
+        if self.text[self.pos..].starts_with(op.as_bytes()) {
+            self.pos += op.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        'operators: loop {
+            for op in LEVELS[level] {
+                if self.eat(op) {
+                    let rhs = self.binary(level + 1)?;
+                    value = apply(op, value, rhs)?;
+                    continue 'operators;
+                }
+            }
+            return Ok(value);
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.eat("-") {
+            return Ok(self.unary()?.wrapping_neg());
+        }
+        if self.eat("~") {
+            return Ok(!self.unary()?);
+        }
+        if self.eat("(") {
+            let value = self.binary(0)?;
+            if !self.eat(")") {
+                return Err("missing `)` in expression".to_string());
+            }
+            return Ok(value);
+        }
+        self.skip_spaces();
+        let start = self.pos;
+        while self.pos < self.text.len() && is_symbol_char(self.text[self.pos]) {
+            self.pos += 1;
+        }
+        let token = String::from_utf8_lossy(&self.text[start..self.pos]);
+        if token.is_empty() {
+            return Err("missing value in expression".to_string());
+        }
+        if token.as_bytes()[0].is_ascii_digit() {
+            parse_number(&token).ok_or_else(|| format!("invalid number `{}`", token))
+        } else {
+            (self.lookup)(&token).ok_or_else(|| format!("undefined symbol `{}`", token))
+        }
+    }
+}
+
+fn apply(op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+    Ok(match op {
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "&" => lhs & rhs,
+        "<<" => lhs << (rhs & 63),
+        ">>" => lhs >> (rhs & 63),
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" if rhs == 0 => return Err("division by zero".to_string()),
+        // only i64::MIN / -1 overflows
+        "/" => lhs.checked_div(rhs).ok_or("overflow")?,
+        _ => lhs.checked_rem(rhs).ok_or("overflow")?,
+    })
+}
@@ -0,0 +1,199 @@
+// Assembler: turns source text into a ROM for Cpu::load_rom, along with a
+// listing that maps every emitted byte back to the line it came from
+//
+// Each syntax lives in its own module, this one has what they share: loading
+// files and includes, laying bytes out in the ROM image and the listing
+
+mod classic;
+mod expr;
+mod octo;
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use super::AsmError;
+
+pub use self::classic::{assemble, assemble_file};
+pub use self::octo::{assemble_octo, assemble_octo_file};
+
+// Where ROMs are loaded
+const START: u32 = 0x200;
+// Includes nested deeper than this are most likely including themselves
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// The bytes one source line turned into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub file: String,
+    pub line: usize,
+    pub source: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    // Starts at 0x200, gaps left by ORG are zeros
+    pub rom: Vec<u8>,
+    // In address order
+    pub listing: Vec<Listing>,
+}
+
+impl Assembly {
+    // The listing as text, address and bytes first, long data split over
+    // several lines
+    pub fn listing_text(&self) -> String {
+        let mut out = String::new();
+        for entry in self.listing.iter() {
+            for (n, chunk) in entry.bytes.chunks(4).enumerate() {
+                let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+                let addr = entry.addr as usize + n * 4;
+                if n == 0 {
+                    writeln!(
+                        out,
+                        "{:03X}  {:<11}  {}:{}  {}",
+                        addr,
+                        hex.join(" "),
+                        entry.file,
+                        entry.line,
+                        entry.source.trim()
+                    )
+                    .unwrap();
+                } else {
+                    writeln!(out, "{:03X}  {}", addr, hex.join(" ")).unwrap();
+                }
+            }
+        }
+        out
+    }
+}
+
+// A line of source once includes are expanded, line counts from 1
+struct SourceLine {
+    file: String,
+    line: usize,
+    text: String,
+}
+
+impl SourceLine {
+    fn error<M: Into<String>>(&self, message: M) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+}
+
+// Splits the source into lines, replacing the ones include() recognises with
+// the lines of the file they name, relative to the including file
+fn load(
+    file: &str,
+    text: &str,
+    include: fn(&str) -> Result<Option<String>, String>,
+    depth: usize,
+    lines: &mut Vec<SourceLine>,
+) -> Result<(), AsmError> {
+    for (n, text) in text.lines().enumerate() {
+        let line = SourceLine {
+            file: file.to_string(),
+            line: n + 1,
+            text: text.to_string(),
+        };
+        let name = match include(text) {
+            Ok(Some(name)) => name,
+            Ok(None) => {
+                lines.push(line);
+                continue;
+            }
+            Err(message) => return Err(line.error(message)),
+        };
+        if depth == MAX_INCLUDE_DEPTH {
+            return Err(line.error("includes nested too deep"));
+        }
+        let path = Path::new(file)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&name);
+        let included = fs::read_to_string(&path)
+            .map_err(|err| line.error(format!("couldn't read {}: {}", path.display(), err)))?;
+        load(
+            &path.to_string_lossy(),
+            &included,
+            include,
+            depth + 1,
+            lines,
+        )?;
+    }
+    Ok(())
+}
+
+fn read(
+    path: &Path,
+    include: fn(&str) -> Result<Option<String>, String>,
+) -> Result<Vec<SourceLine>, AsmError> {
+    let file = path.to_string_lossy();
+    let text = fs::read_to_string(path).map_err(|err| AsmError {
+        file: file.to_string(),
+        line: 0,
+        message: format!("couldn't read: {}", err),
+    })?;
+    let mut lines = Vec::new();
+    load(&file, &text, include, 0, &mut lines)?;
+    Ok(lines)
+}
+
+// The ROM being built
+#[derive(Default)]
+struct Image {
+    rom: Vec<u8>,
+    used: Vec<bool>,
+    listing: Vec<Listing>,
+}
+
+impl Image {
+    fn emit(&mut self, addr: u32, bytes: Vec<u8>, source: &SourceLine) -> Result<(), AsmError> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let end = addr + bytes.len() as u32;
+        if addr < START || end > 0x10000 {
+            return Err(source.error(format!("address {:#X} is outside the ROM", addr)));
+        }
+        let offset = (addr - START) as usize;
+        if self.rom.len() < offset + bytes.len() {
+            self.rom.resize(offset + bytes.len(), 0);
+            self.used.resize(offset + bytes.len(), false);
+        }
+        if let Some(n) = self.used[offset..offset + bytes.len()]
+            .iter()
+            .position(|&used| used)
+        {
+            return Err(source.error(format!(
+                "overwrites the byte already at {:03X}",
+                addr as usize + n
+            )));
+        }
+        self.rom[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        for used in self.used[offset..offset + bytes.len()].iter_mut() {
+            *used = true;
+        }
+        self.listing.push(Listing {
+            addr: addr as u16,
+            bytes,
+            file: source.file.clone(),
+            line: source.line,
+            source: source.text.clone(),
+        });
+        Ok(())
+    }
+
+    fn finish(mut self) -> Assembly {
+        self.listing.sort_by_key(|entry| entry.addr);
+        Assembly {
+            rom: self.rom,
+            listing: self.listing,
+        }
+    }
+}
@@ -0,0 +1,877 @@
+// Octo syntax, what most of the modern CHIP-8 programs are written in
+//
+//     :const SPEED 3
+//     :alias x v1
+//     :macro step reg { reg += SPEED }
+//     : main
+//         i := smile
+//         loop
+//             sprite x v2 4
+//             step x
+//             if x == 60 then x := 0
+//             sprite x v2 4
+//         again
+//     : smile 0x24 0x00 0x81 0x7E
+//
+// Everything is whitespace separated tokens, `#` starts a comment. Constants
+// and calcs can only use the names defined above them, labels can be used
+// anywhere an address goes. Execution starts at 0x200, a program whose
+// `main` label is somewhere else gets a `jump main` there
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::super::{AsmError, Instruction};
+
+use super::expr::{self, parse_number};
+use super::{load, read, Assembly, Image, SourceLine, START};
+
+type Symbols = HashMap<String, i64>;
+
+// Macros calling themselves forever stop here
+const MAX_EXPANSIONS: usize = 65536;
+
+pub fn assemble_octo(source: &str) -> Result<Assembly, AsmError> {
+    let mut lines = Vec::new();
+    load("<source>", source, |_| Ok(None), 0, &mut lines)?;
+    assemble_lines(&lines)
+}
+
+pub fn assemble_octo_file<P: AsRef<Path>>(path: P) -> Result<Assembly, AsmError> {
+    assemble_lines(&read(path.as_ref(), |_| Ok(None))?)
+}
+
+// Code sizes never depend on where labels end up, so a first run with every
+// forward reference at 0 finds them all and a second one uses them. When
+// main isn't the first thing there's a run in between to lay the code out
+// after the jump
+fn assemble_lines(lines: &[SourceLine]) -> Result<Assembly, AsmError> {
+    let first = Compiler::run(lines, None, false)?;
+    let jump = first
+        .symbols
+        .get("main")
+        .is_some_and(|&main| main != i64::from(START));
+    let labels = if jump {
+        Compiler::run(lines, None, true)?.symbols
+    } else {
+        first.symbols
+    };
+    Compiler::run(lines, Some(&labels), jump)?.finish()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    // index in the lines
+    line: usize,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+// Blocks waiting for their closing word, numbered to name the labels they jump to
+enum Flow {
+    If {
+        n: usize,
+        line: usize,
+        has_else: bool,
+    },
+    Loop {
+        n: usize,
+        line: usize,
+        start: u32,
+    },
+}
+
+struct Compiler<'a> {
+    lines: &'a [SourceLine],
+    // the rest of the source, last token first
+    tokens: Vec<Token>,
+    // labels found by the previous run, none on the first one
+    known: Option<&'a Symbols>,
+    symbols: Symbols,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    flow: Vec<Flow>,
+    blocks: usize,
+    expansions: usize,
+    jump_to_main: bool,
+    main_line: usize,
+    pc: u32,
+    // the statement being compiled
+    start: u32,
+    bytes: Vec<u8>,
+    image: Image,
+}
+
+impl<'a> Compiler<'a> {
+    fn run(
+        lines: &'a [SourceLine],
+        known: Option<&'a Symbols>,
+        jump_to_main: bool,
+    ) -> Result<Compiler<'a>, AsmError> {
+        let mut tokens = Vec::new();
+        for (n, line) in lines.iter().enumerate() {
+            let text = line.text.split('#').next().unwrap_or("");
+            tokens.extend(text.split_whitespace().map(|text| Token {
+                text: text.to_string(),
+                line: n,
+            }));
+        }
+        tokens.reverse();
+
+        let start = if jump_to_main { START + 2 } else { START };
+        let mut compiler = Compiler {
+            lines,
+            tokens,
+            known,
+            symbols: Symbols::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            flow: Vec::new(),
+            blocks: 0,
+            expansions: 0,
+            jump_to_main,
+            main_line: 0,
+            pc: start,
+            start,
+            bytes: Vec::new(),
+            image: Image::default(),
+        };
+        while let Some(token) = compiler.tokens.pop() {
+            compiler.start = compiler.pc;
+            compiler.statement(&token)?;
+            let bytes = std::mem::take(&mut compiler.bytes);
+            compiler
+                .image
+                .emit(compiler.start, bytes, &lines[token.line])?;
+        }
+        if let Some(flow) = compiler.flow.last() {
+            let (line, message) = match *flow {
+                Flow::If { line, .. } => (line, "`begin` without `end`"),
+                Flow::Loop { line, .. } => (line, "`loop` without `again`"),
+            };
+            return Err(lines[line].error(message));
+        }
+        Ok(compiler)
+    }
+
+    fn finish(mut self) -> Result<Assembly, AsmError> {
+        if self.jump_to_main {
+            let source = &self.lines[self.main_line];
+            let main = self.symbols["main"];
+            if main > 0xFFF {
+                return Err(source.error(format!("address {:#X} is out of range", main)));
+            }
+            let opcode = Instruction::Jp { nnn: main as u16 }.encode();
+            self.image
+                .emit(START, vec![(opcode >> 8) as u8, opcode as u8], source)?;
+        }
+        Ok(self.image.finish())
+    }
+
+    fn error<M: Into<String>>(&self, token: &Token, message: M) -> AsmError {
+        self.lines[token.line].error(message)
+    }
+
+    fn next(&mut self, after: &Token) -> Result<Token, AsmError> {
+        match self.tokens.pop() {
+            Some(token) => Ok(token),
+            None => Err(self.error(
+                after,
+                format!("unexpected end of source after `{}`", after.text),
+            )),
+        }
+    }
+
+    fn expect(&mut self, after: &Token, text: &str) -> Result<Token, AsmError> {
+        let token = self.next(after)?;
+        if token.text != text {
+            return Err(self.error(
+                &token,
+                format!("expected `{}`, found `{}`", text, token.text),
+            ));
+        }
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        let opcode = instruction.encode();
+        self.bytes
+            .extend_from_slice(&[(opcode >> 8) as u8, opcode as u8]);
+        if let Instruction::LdILong { nnnn } = instruction {
+            self.bytes
+                .extend_from_slice(&[(nnnn >> 8) as u8, nnnn as u8]);
+        }
+        self.pc += u32::from(instruction.size());
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        self.bytes.push(byte);
+        self.pc += 1;
+    }
+
+    fn statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        use self::Instruction::*;
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next(token)?;
+                if name.text == "main" {
+                    self.main_line = name.line;
+                }
+                let pc = i64::from(self.pc);
+                self.define(&name, pc)?;
+            }
+            ":next" => {
+                // the second byte of the next instruction, for self-modifying code
+                let name = self.next(token)?;
+                let pc = i64::from(self.pc) + 1;
+                self.define(&name, pc)?;
+            }
+            ":const" => {
+                let name = self.next(token)?;
+                let value = self.value(&name)?;
+                self.define(&name, value)?;
+            }
+            ":calc" => {
+                let name = self.next(token)?;
+                let open = self.expect(&name, "{")?;
+                let value = self.calc(&open)?;
+                self.define(&name, value)?;
+            }
+            ":alias" => {
+                let name = self.next(token)?;
+                let x = if self.peek() == Some("{") {
+                    let open = self.next(&name)?;
+                    let value = self.calc(&open)?;
+                    if !(0..=15).contains(&value) {
+                        return Err(
+                            self.error(&open, format!("register {} is out of range", value))
+                        );
+                    }
+                    value as u8
+                } else {
+                    self.register(&name)?
+                };
+                self.check_name(&name)?;
+                self.aliases.insert(name.text, x);
+            }
+            ":macro" => self.macro_definition(token)?,
+            ":org" => {
+                let value = self.value(token)?;
+                if !(i64::from(START)..=0xFFFF).contains(&value) {
+                    return Err(self.error(token, format!(":org {:#X} is outside the ROM", value)));
+                }
+                self.pc = value as u32;
+                self.start = self.pc;
+            }
+            ":byte" => {
+                let byte = self.byte(token)?;
+                self.emit_byte(byte);
+            }
+            ":pointer" => {
+                let addr = self.address(token, 0xFFFF)?;
+                self.emit_byte((addr >> 8) as u8);
+                self.emit_byte(addr as u8);
+            }
+            ":call" => {
+                let nnn = self.address(token, 0xFFF)?;
+                self.emit(Call { nnn });
+            }
+            ":unpack" => {
+                // v0 and v1 get an address, a nibble can go on top of it
+                let (high, addr) = if self.peek() == Some("long") {
+                    let long = self.next(token)?;
+                    (0, self.address(&long, 0xFFFF)?)
+                } else {
+                    let nibble = self.value(token)?;
+                    if !(0..=15).contains(&nibble) {
+                        return Err(self.error(token, format!("nibble {} is out of range", nibble)));
+                    }
+                    ((nibble as u8) << 4, self.address(token, 0xFFF)?)
+                };
+                self.emit(LdByte {
+                    x: 0,
+                    nn: high | (addr >> 8) as u8,
+                });
+                self.emit(LdByte {
+                    x: 1,
+                    nn: addr as u8,
+                });
+            }
+            // debugger hints, nothing to emit
+            ":breakpoint" => {
+                self.next(token)?;
+            }
+            ":monitor" => {
+                self.next(token)?;
+                self.next(token)?;
+            }
+            "return" | ";" => self.emit(Ret),
+            "clear" => self.emit(Cls),
+            "hires" => self.emit(High),
+            "lores" => self.emit(Low),
+            "exit" => self.emit(Exit),
+            "scroll-left" => self.emit(Scl),
+            "scroll-right" => self.emit(Scr),
+            "audio" => self.emit(Audio),
+            "scroll-down" => {
+                let n = self.nibble(token)?;
+                self.emit(Scd { n });
+            }
+            "scroll-up" => {
+                let n = self.nibble(token)?;
+                self.emit(Scu { n });
+            }
+            "plane" => {
+                let n = self.nibble(token)?;
+                self.emit(Plane { n });
+            }
+            "bcd" => {
+                let x = self.register(token)?;
+                self.emit(LdBVx { x });
+            }
+            "save" | "load" => {
+                let x = self.register(token)?;
+                let instruction = if self.peek() == Some("-") {
+                    let dash = self.next(token)?;
+                    let y = self.register(&dash)?;
+                    if token.text == "save" {
+                        SaveRange { x, y }
+                    } else {
+                        LoadRange { x, y }
+                    }
+                } else if token.text == "save" {
+                    LdIVx { x }
+                } else {
+                    LdVxI { x }
+                };
+                self.emit(instruction);
+            }
+            "saveflags" => {
+                let x = self.register(token)?;
+                self.emit(LdRVx { x });
+            }
+            "loadflags" => {
+                let x = self.register(token)?;
+                self.emit(LdVxR { x });
+            }
+            "sprite" => {
+                let x = self.register(token)?;
+                let y = self.register(token)?;
+                let n = self.nibble(token)?;
+                self.emit(Drw { x, y, n });
+            }
+            "jump" => {
+                let nnn = self.address(token, 0xFFF)?;
+                self.emit(Jp { nnn });
+            }
+            "jump0" => {
+                let nnn = self.address(token, 0xFFF)?;
+                self.emit(JpV0 { nnn });
+            }
+            "native" => {
+                let nnn = self.address(token, 0xFFF)?;
+                self.emit(Sys { nnn });
+            }
+            "i" => self.i_statement(token)?,
+            "delay" | "buzzer" | "pitch" => {
+                let assign = self.expect(token, ":=")?;
+                let x = self.register(&assign)?;
+                self.emit(match token.text.as_str() {
+                    "delay" => LdDtVx { x },
+                    "buzzer" => LdStVx { x },
+                    _ => Pitch { x },
+                });
+            }
+            "if" => self.if_statement(token)?,
+            "else" => {
+                let n = match self.flow.last_mut() {
+                    Some(&mut Flow::If {
+                        n,
+                        ref mut has_else,
+                        ..
+                    }) if !*has_else => {
+                        *has_else = true;
+                        n
+                    }
+                    _ => return Err(self.error(token, "`else` without `begin`")),
+                };
+                self.jump_to(token, &format!("end {}", n))?;
+                let pc = i64::from(self.pc);
+                self.symbols.insert(format!("else {}", n), pc);
+            }
+            "end" => {
+                let (n, has_else) = match self.flow.pop() {
+                    Some(Flow::If { n, has_else, .. }) => (n, has_else),
+                    _ => return Err(self.error(token, "`end` without `begin`")),
+                };
+                let label = if has_else { "end" } else { "else" };
+                let pc = i64::from(self.pc);
+                self.symbols.insert(format!("{} {}", label, n), pc);
+            }
+            "loop" => {
+                self.flow.push(Flow::Loop {
+                    n: self.blocks,
+                    line: token.line,
+                    start: self.pc,
+                });
+                self.blocks += 1;
+            }
+            "while" => {
+                let n = match self.flow.iter().rev().find_map(|flow| match *flow {
+                    Flow::Loop { n, .. } => Some(n),
+                    Flow::If { .. } => None,
+                }) {
+                    Some(n) => n,
+                    None => return Err(self.error(token, "`while` outside a loop")),
+                };
+                let (setup, skip) = self.condition(token)?;
+                for instruction in setup {
+                    self.emit(instruction);
+                }
+                self.emit(negate(skip));
+                self.jump_to(token, &format!("break {}", n))?;
+            }
+            "again" => {
+                let (n, start) = match self.flow.pop() {
+                    Some(Flow::Loop { n, start, .. }) => (n, start),
+                    _ => return Err(self.error(token, "`again` without `loop`")),
+                };
+                let nnn = self.check_address(token, i64::from(start), 0xFFF)?;
+                self.emit(Jp { nnn });
+                let pc = i64::from(self.pc);
+                self.symbols.insert(format!("break {}", n), pc);
+            }
+            text if self.is_register(text) => self.register_statement(token)?,
+            text if self.macros.contains_key(text) => self.expand(token)?,
+            // anything else is data, or a call when it's a name
+            text => match number(text) {
+                Some(value) => {
+                    let byte = self.check_byte(token, value)?;
+                    self.emit_byte(byte);
+                }
+                None => {
+                    let nnn = self.resolve(token, 0xFFF)?;
+                    self.emit(Call { nnn });
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn i_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        let op = self.next(token)?;
+        match op.text.as_str() {
+            "+=" => {
+                let x = self.register(&op)?;
+                self.emit(Instruction::AddIVx { x });
+            }
+            ":=" => match self.peek() {
+                Some("hex") | Some("bighex") => {
+                    let hex = self.next(&op)?;
+                    let x = self.register(&hex)?;
+                    self.emit(if hex.text == "hex" {
+                        Instruction::LdFVx { x }
+                    } else {
+                        Instruction::LdHfVx { x }
+                    });
+                }
+                Some("long") => {
+                    let long = self.next(&op)?;
+                    let nnnn = self.address(&long, 0xFFFF)?;
+                    self.emit(Instruction::LdILong { nnnn });
+                }
+                _ => {
+                    let nnn = self.address(&op, 0xFFF)?;
+                    self.emit(Instruction::LdI { nnn });
+                }
+            },
+            _ => return Err(self.error(&op, format!("expected `:=` or `+=`, found `{}`", op.text))),
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        use self::Instruction::*;
+
+        let x = self.register_of(token)?;
+        let op = self.next(token)?;
+        if op.text == ":=" {
+            match self.peek() {
+                Some("random") => {
+                    let random = self.next(&op)?;
+                    let nn = self.byte(&random)?;
+                    self.emit(Rnd { x, nn });
+                    return Ok(());
+                }
+                Some("key") => {
+                    self.next(&op)?;
+                    self.emit(LdVxK { x });
+                    return Ok(());
+                }
+                Some("delay") => {
+                    self.next(&op)?;
+                    self.emit(LdVxDt { x });
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+
+        let register = self.peek().is_some_and(|text| self.is_register(text));
+        let instruction = if register {
+            let y = self.register(&op)?;
+            match op.text.as_str() {
+                ":=" => LdReg { x, y },
+                "+=" => AddReg { x, y },
+                "-=" => Sub { x, y },
+                "=-" => Subn { x, y },
+                "|=" => Or { x, y },
+                "&=" => And { x, y },
+                "^=" => Xor { x, y },
+                ">>=" => Shr { x, y },
+                "<<=" => Shl { x, y },
+                _ => return Err(self.error(&op, format!("unknown operator `{}`", op.text))),
+            }
+        } else {
+            match op.text.as_str() {
+                ":=" => LdByte {
+                    x,
+                    nn: self.byte(&op)?,
+                },
+                "+=" => AddByte {
+                    x,
+                    nn: self.byte(&op)?,
+                },
+                "-=" => AddByte {
+                    x,
+                    nn: self.byte(&op)?.wrapping_neg(),
+                },
+                _ => return Err(self.error(&op, format!("`{}` needs a register", op.text))),
+            }
+        };
+        self.emit(instruction);
+        Ok(())
+    }
+
+    fn if_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        let (setup, skip) = self.condition(token)?;
+        for instruction in setup {
+            self.emit(instruction);
+        }
+        let word = self.next(token)?;
+        match word.text.as_str() {
+            // the skip jumps over the next statement
+            "then" => self.emit(skip),
+            "begin" => {
+                let n = self.blocks;
+                self.blocks += 1;
+                self.emit(negate(skip));
+                self.jump_to(&word, &format!("else {}", n))?;
+                self.flow.push(Flow::If {
+                    n,
+                    line: token.line,
+                    has_else: false,
+                });
+            }
+            _ => {
+                return Err(self.error(
+                    &word,
+                    format!("expected `then` or `begin`, found `{}`", word.text),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    // The instructions to test a condition and the skip that jumps over the
+    // next one when it's false. Comparisons go through VF
+    fn condition(&mut self, token: &Token) -> Result<(Vec<Instruction>, Instruction), AsmError> {
+        use self::Instruction::*;
+
+        let x = self.register(token)?;
+        let op = self.next(token)?;
+        match op.text.as_str() {
+            "key" => return Ok((vec![], Sknp { x })),
+            "-key" => return Ok((vec![], Skp { x })),
+            _ => {}
+        }
+        let register = self.peek().is_some_and(|text| self.is_register(text));
+        let (y, nn) = if register {
+            (self.register(&op)?, 0)
+        } else {
+            (0, self.byte(&op)?)
+        };
+        Ok(match (op.text.as_str(), register) {
+            ("==", true) => (vec![], SneReg { x, y }),
+            ("==", false) => (vec![], SneByte { x, nn }),
+            ("!=", true) => (vec![], SeReg { x, y }),
+            ("!=", false) => (vec![], SeByte { x, nn }),
+            // VF = 1 when VX >= the other side
+            ("<", _) | (">=", _) => {
+                let setup = if register {
+                    vec![LdReg { x: 0xF, y: x }, Sub { x: 0xF, y }]
+                } else {
+                    vec![LdByte { x: 0xF, nn }, Subn { x: 0xF, y: x }]
+                };
+                let skip = if op.text == "<" {
+                    SneByte { x: 0xF, nn: 0 }
+                } else {
+                    SeByte { x: 0xF, nn: 0 }
+                };
+                (setup, skip)
+            }
+            // VF = 1 when the other side >= VX
+            (">", _) | ("<=", _) => {
+                let setup = if register {
+                    vec![LdReg { x: 0xF, y }, Sub { x: 0xF, y: x }]
+                } else {
+                    vec![LdByte { x: 0xF, nn }, Sub { x: 0xF, y: x }]
+                };
+                let skip = if op.text == ">" {
+                    SneByte { x: 0xF, nn: 0 }
+                } else {
+                    SeByte { x: 0xF, nn: 0 }
+                };
+                (setup, skip)
+            }
+            _ => return Err(self.error(&op, format!("unknown comparison `{}`", op.text))),
+        })
+    }
+
+    // A jump to one of the flow control labels, errors point at token
+    fn jump_to(&mut self, token: &Token, label: &str) -> Result<(), AsmError> {
+        let nnn = match self
+            .symbols
+            .get(label)
+            .or_else(|| self.known.and_then(|known| known.get(label)))
+        {
+            Some(&addr) => self.check_address(token, addr, 0xFFF)?,
+            None => 0,
+        };
+        self.emit(Instruction::Jp { nnn });
+        Ok(())
+    }
+
+    fn macro_definition(&mut self, token: &Token) -> Result<(), AsmError> {
+        let name = self.next(token)?;
+        let mut args = Vec::new();
+        loop {
+            let arg = self.next(&name)?;
+            if arg.text == "{" {
+                break;
+            }
+            args.push(arg.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next(&name)?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+        self.check_name(&name)?;
+        self.macros.insert(name.text, Macro { args, body });
+        Ok(())
+    }
+
+    // Pushes the body back into the source with the arguments in place,
+    // everything in it reports the line of the call
+    fn expand(&mut self, token: &Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error(token, "too many macro expansions"));
+        }
+        let count = self.macros[&token.text].args.len();
+        let mut values = HashMap::new();
+        for n in 0..count {
+            let value = self.next(token)?;
+            values.insert(self.macros[&token.text].args[n].clone(), value.text);
+        }
+        let body: Vec<Token> = self.macros[&token.text]
+            .body
+            .iter()
+            .rev()
+            .map(|body| Token {
+                text: values.get(&body.text).unwrap_or(&body.text).clone(),
+                line: token.line,
+            })
+            .collect();
+        self.tokens.extend(body);
+        Ok(())
+    }
+
+    fn is_register(&self, text: &str) -> bool {
+        register(text).is_some() || self.aliases.contains_key(text)
+    }
+
+    fn register_of(&self, token: &Token) -> Result<u8, AsmError> {
+        register(&token.text)
+            .or_else(|| self.aliases.get(&token.text).cloned())
+            .ok_or_else(|| {
+                self.error(
+                    token,
+                    format!("expected a register, found `{}`", token.text),
+                )
+            })
+    }
+
+    fn register(&mut self, after: &Token) -> Result<u8, AsmError> {
+        let token = self.next(after)?;
+        self.register_of(&token)
+    }
+
+    fn check_name(&self, name: &Token) -> Result<(), AsmError> {
+        let text = name.text.as_str();
+        if register(text).is_some()
+            || number(text).is_some()
+            || text.starts_with(':')
+            || text == "{"
+            || text == "}"
+        {
+            return Err(self.error(name, format!("`{}` can't be a name", text)));
+        }
+        if self.symbols.contains_key(text)
+            || self.aliases.contains_key(text)
+            || self.macros.contains_key(text)
+        {
+            return Err(self.error(name, format!("`{}` is already defined", text)));
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &Token, value: i64) -> Result<(), AsmError> {
+        self.check_name(name)?;
+        self.symbols.insert(name.text.clone(), value);
+        Ok(())
+    }
+
+    // The expression up to the closing brace, HERE is the current address
+    fn calc(&mut self, open: &Token) -> Result<i64, AsmError> {
+        let mut text = String::new();
+        loop {
+            let token = self.next(open)?;
+            if token.text == "}" {
+                break;
+            }
+            text.push_str(&token.text);
+            text.push(' ');
+        }
+        let pc = i64::from(self.pc);
+        let symbols = &self.symbols;
+        expr::eval(&text, &|name| match name {
+            "HERE" => Some(pc),
+            _ => symbols.get(name).cloned(),
+        })
+        .map_err(|message| self.error(open, message))
+    }
+
+    // A number, a name defined above or a calc in braces
+    fn value(&mut self, after: &Token) -> Result<i64, AsmError> {
+        let token = self.next(after)?;
+        if token.text == "{" {
+            return self.calc(&token);
+        }
+        match number(&token.text).or_else(|| self.symbols.get(&token.text).cloned()) {
+            Some(value) => Ok(value),
+            None => Err(self.error(&token, format!("undefined name `{}`", token.text))),
+        }
+    }
+
+    fn byte(&mut self, after: &Token) -> Result<u8, AsmError> {
+        let value = self.value(after)?;
+        self.check_byte(after, value)
+    }
+
+    fn check_byte(&self, token: &Token, value: i64) -> Result<u8, AsmError> {
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(token, format!("byte {} is out of range", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self, after: &Token) -> Result<u8, AsmError> {
+        let value = self.value(after)?;
+        if !(0..=15).contains(&value) {
+            return Err(self.error(after, format!("nibble {} is out of range", value)));
+        }
+        Ok(value as u8)
+    }
+
+    // Like value() but labels further down are fine too
+    fn address(&mut self, after: &Token, max: i64) -> Result<u16, AsmError> {
+        let token = self.next(after)?;
+        if token.text == "{" {
+            let value = self.calc(&token)?;
+            return self.check_address(&token, value, max);
+        }
+        self.resolve(&token, max)
+    }
+
+    fn resolve(&self, token: &Token, max: i64) -> Result<u16, AsmError> {
+        let value = number(&token.text)
+            .or_else(|| self.symbols.get(&token.text).cloned())
+            .or_else(|| self.known.and_then(|known| known.get(&token.text).cloned()));
+        match (value, self.known) {
+            (Some(value), _) => self.check_address(token, value, max),
+            // the first run doesn't know the labels further down yet
+            (None, None) => Ok(0),
+            (None, Some(_)) => Err(self.error(token, format!("undefined name `{}`", token.text))),
+        }
+    }
+
+    fn check_address(&self, token: &Token, value: i64, max: i64) -> Result<u16, AsmError> {
+        if !(0..=max).contains(&value) {
+            return Err(self.error(token, format!("address {:#X} is out of range", value)));
+        }
+        Ok(value as u16)
+    }
+}
+
+// The skip with the opposite condition
+fn negate(skip: Instruction) -> Instruction {
+    use self::Instruction::*;
+
+    match skip {
+        SeByte { x, nn } => SneByte { x, nn },
+        SneByte { x, nn } => SeByte { x, nn },
+        SeReg { x, y } => SneReg { x, y },
+        SneReg { x, y } => SeReg { x, y },
+        Skp { x } => Sknp { x },
+        Sknp { x } => Skp { x },
+        other => other,
+    }
+}
+
+fn register(text: &str) -> Option<u8> {
+    let bytes = text.as_bytes();
+    if bytes.len() == 2 && (bytes[0] == b'v' || bytes[0] == b'V') {
+        u8::from_str_radix(&text[1..], 16).ok()
+    } else {
+        None
+    }
+}
+
+// Octo numbers can have a minus sign in front
+fn number(text: &str) -> Option<i64> {
+    match text.strip_prefix('-') {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => {
+            parse_number(rest).map(|n| -n)
+        }
+        _ if text.starts_with(|c: char| c.is_ascii_digit()) => parse_number(text),
+        _ => None,
+    }
+}
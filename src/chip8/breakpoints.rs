@@ -0,0 +1,356 @@
+// Breakpoints and watchpoints for debuggers
+//
+// Breakpoints on pc and opcodes stop before the instruction runs, cpu_tick
+// returns StepOutcome::Break with pc still pointing at it and the next
+// cpu_tick runs it without breaking again. Watchpoints and conditions stop
+// after the instruction that triggered them, pc already points to the next one
+//
+// There's room for MAX_BREAKPOINTS of them, in a fixed array so the core
+// still doesn't allocate
+
+use super::{Cpu, CpuError, Instruction, Random, StepOutcome};
+
+pub const MAX_BREAKPOINTS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    // Before the instruction at this address runs
+    Pc(u16),
+    // Same, but only when the condition holds right then
+    PcIf(u16, Condition),
+    // After an instruction makes the condition true, it has to become false
+    // again before it fires another time
+    Condition(Condition),
+    // After an instruction reads or writes memory in start..=end
+    Memory {
+        start: u16,
+        end: u16,
+        access: Access,
+    },
+    // After an instruction reads or writes I
+    I(Access),
+    // Before any instruction with opcode & mask == value runs, e.g. mask
+    // 0xF000 and value 0xD000 for every DXYN or 0xF0FF and 0xF00A for FX0A
+    Opcode {
+        mask: u16,
+        value: u16,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// register <comparison> value, e.g. V3 >= 10
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+// Which breakpoint stopped the program and the address of the instruction
+// that triggered it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakpointHit {
+    pub id: usize,
+    pub breakpoint: Breakpoint,
+    pub pc: u16,
+    // for memory watchpoints the first address the instruction accessed
+    // inside the range
+    pub addr: Option<u16>,
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    breakpoint: Breakpoint,
+    // whether the condition held after the last instruction
+    was_true: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct Breakpoints {
+    slots: [Option<Slot>; MAX_BREAKPOINTS],
+    len: usize,
+    // the last tick stopped before the instruction here, the next one runs it
+    resume: Option<u16>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints {
+            slots: [None; MAX_BREAKPOINTS],
+            len: 0,
+            resume: None,
+        }
+    }
+}
+
+impl Breakpoint {
+    // Whether it stops before the instruction runs, with pc still on it
+    pub fn before(&self) -> bool {
+        matches!(
+            *self,
+            Breakpoint::Pc(_) | Breakpoint::PcIf(..) | Breakpoint::Opcode { .. }
+        )
+    }
+}
+
+impl Access {
+    fn includes(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+impl Comparison {
+    fn compare(self, a: u16, b: u16) -> bool {
+        match self {
+            Comparison::Eq => a == b,
+            Comparison::Ne => a != b,
+            Comparison::Lt => a < b,
+            Comparison::Le => a <= b,
+            Comparison::Gt => a > b,
+            Comparison::Ge => a >= b,
+        }
+    }
+}
+
+// What an instruction is about to do with memory and I
+pub(super) struct Effects {
+    // access, first address and length
+    pub(super) memory: Option<(Access, u32, u32)>,
+    reads_i: bool,
+    writes_i: bool,
+}
+
+impl<R: Random> Cpu<R> {
+    // Returns the id to remove it with, or None when all the slots are taken
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> Option<usize> {
+        let was_true = match breakpoint {
+            Breakpoint::Condition(condition) => self.holds(condition),
+            _ => false,
+        };
+        let id = self
+            .breakpoints
+            .slots
+            .iter()
+            .position(|slot| slot.is_none())?;
+        self.breakpoints.slots[id] = Some(Slot {
+            breakpoint,
+            was_true,
+        });
+        self.breakpoints.len += 1;
+        Some(id)
+    }
+
+    // false if there was no breakpoint with that id
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        match self.breakpoints.slots.get_mut(id) {
+            Some(slot) if slot.is_some() => {
+                *slot = None;
+                self.breakpoints.len -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints = Breakpoints::new();
+    }
+
+    // The breakpoints with their ids
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, Breakpoint)> + '_ {
+        self.breakpoints
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(id, slot)| slot.map(|slot| (id, slot.breakpoint)))
+    }
+
+    // Run up to `instructions` instructions, stopping early at a breakpoint,
+    // an exit or when the program waits for a key or the next frame. Returns
+    // what the last cpu_tick did
+    pub fn run(&mut self, instructions: usize) -> Result<StepOutcome, CpuError> {
+        let mut outcome = StepOutcome::Executed;
+        for _ in 0..instructions {
+            outcome = self.cpu_tick()?;
+            if outcome != StepOutcome::Executed {
+                break;
+            }
+        }
+        Ok(outcome)
+    }
+
+    // cpu_tick with the breakpoints checked around execute
+    pub(super) fn execute_checked(
+        &mut self,
+        instruction: Instruction,
+    ) -> Result<StepOutcome, CpuError> {
+        let pc = self.opcode_pc;
+        if self.breakpoints.len == 0 {
+            return self.execute(instruction);
+        }
+
+        if self.breakpoints.resume.take() != Some(pc) {
+            if let Some(hit) = self.break_before(pc, instruction) {
+                self.pc = pc;
+                self.breakpoints.resume = Some(pc);
+                return Ok(StepOutcome::Break(hit));
+            }
+        }
+
+        let effects = self.effects(instruction);
+        let i = self.i;
+        let outcome = self.execute(instruction)?;
+        // FX0A waiting for a key or 00FD runs again next tick, a breakpoint
+        // on it already fired
+        if outcome == StepOutcome::WaitingForKey || outcome == StepOutcome::Exited {
+            self.breakpoints.resume = Some(pc);
+        }
+        let writes_i = effects.writes_i || self.i != i;
+        match self.break_after(pc, &effects, writes_i) {
+            Some(hit) => Ok(StepOutcome::Break(hit)),
+            None => Ok(outcome),
+        }
+    }
+
+    fn break_before(&self, pc: u16, instruction: Instruction) -> Option<BreakpointHit> {
+        let opcode = instruction.encode();
+        self.find(pc, |slot| match slot.breakpoint {
+            Breakpoint::Pc(addr) => addr == pc,
+            Breakpoint::PcIf(addr, condition) => addr == pc && self.holds(condition),
+            Breakpoint::Opcode { mask, value } => opcode & mask == value,
+            _ => false,
+        })
+    }
+
+    fn break_after(&mut self, pc: u16, effects: &Effects, writes_i: bool) -> Option<BreakpointHit> {
+        // every condition has to be updated, even after one fired
+        let mut conditions = None;
+        for id in 0..MAX_BREAKPOINTS {
+            if let Some(Slot {
+                breakpoint: Breakpoint::Condition(condition),
+                was_true,
+            }) = self.breakpoints.slots[id]
+            {
+                let is_true = self.holds(condition);
+                if is_true && !was_true && conditions.is_none() {
+                    conditions = Some(BreakpointHit {
+                        id,
+                        breakpoint: Breakpoint::Condition(condition),
+                        pc,
+                        addr: None,
+                    });
+                }
+                self.breakpoints.slots[id] = Some(Slot {
+                    breakpoint: Breakpoint::Condition(condition),
+                    was_true: is_true,
+                });
+            }
+        }
+
+        let watch = self
+            .find(pc, |slot| match slot.breakpoint {
+                Breakpoint::Memory { start, end, access } => match effects.memory {
+                    Some((kind, first, len)) => {
+                        access.includes(kind)
+                            && len > 0
+                            && first <= u32::from(end)
+                            && u32::from(start) < first + len
+                    }
+                    None => false,
+                },
+                Breakpoint::I(access) => {
+                    (effects.reads_i && access.includes(Access::Read))
+                        || (writes_i && access.includes(Access::Write))
+                }
+                _ => false,
+            })
+            .map(|hit| match (hit.breakpoint, effects.memory) {
+                (Breakpoint::Memory { start, .. }, Some((_, first, _))) => BreakpointHit {
+                    addr: Some(first.max(u32::from(start)) as u16),
+                    ..hit
+                },
+                _ => hit,
+            });
+        match (watch, conditions) {
+            (Some(watch), Some(condition)) => Some(if watch.id < condition.id {
+                watch
+            } else {
+                condition
+            }),
+            (watch, condition) => watch.or(condition),
+        }
+    }
+
+    // The first breakpoint, by id, that matches
+    fn find<F: Fn(&Slot) -> bool>(&self, pc: u16, matches: F) -> Option<BreakpointHit> {
+        self.breakpoints
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(id, slot)| slot.map(|slot| (id, slot)))
+            .find(|(_, slot)| matches(slot))
+            .map(|(id, slot)| BreakpointHit {
+                id,
+                breakpoint: slot.breakpoint,
+                pc,
+                addr: None,
+            })
+    }
+
+    fn holds(&self, condition: Condition) -> bool {
+        let value = match condition.register {
+            Register::V(x) => u16::from(self.v[x as usize & 0xF]),
+            Register::I => self.i,
+        };
+        condition.comparison.compare(value, condition.value)
+    }
+
+    pub(super) fn effects(&self, instruction: Instruction) -> Effects {
+        use self::Instruction::*;
+
+        let i = u32::from(self.i);
+        let memory = match instruction {
+            Drw { n, .. } => {
+                let len = if n == 0 { 32 } else { u32::from(n) };
+                Some((Access::Read, i, len * self.plane.count_ones()))
+            }
+            Audio => Some((Access::Read, i, 16)),
+            LdBVx { .. } => Some((Access::Write, i, 3)),
+            LdIVx { x } => Some((Access::Write, i, u32::from(x) + 1)),
+            LdVxI { x } => Some((Access::Read, i, u32::from(x) + 1)),
+            SaveRange { x, y } => Some((Access::Write, i, u32::from(x.max(y) - x.min(y)) + 1)),
+            LoadRange { x, y } => Some((Access::Read, i, u32::from(x.max(y) - x.min(y)) + 1)),
+            _ => None,
+        };
+        Effects {
+            memory,
+            reads_i: memory.is_some() || matches!(instruction, AddIVx { .. }),
+            writes_i: matches!(
+                instruction,
+                LdI { .. } | LdILong { .. } | AddIVx { .. } | LdFVx { .. } | LdHfVx { .. }
+            ),
+        }
+    }
+}
@@ -0,0 +1,367 @@
+// Disassembler: splits a ROM into code and data by following the control flow
+// from the entry point, then prints it with classic or Octo mnemonics
+//
+// Everything reachable from 0x200 through jumps, calls, skips and plain
+// fallthrough is code, the rest (sprites, tables, code only reached through
+// BNNN with V0 != 0) is data and printed as bytes
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::Instruction;
+
+// Where ROMs are loaded
+const START: u16 = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line {
+    Code { addr: u16, instruction: Instruction },
+    Data { addr: u16, byte: u8 },
+}
+
+// Why an address got a label, when there's more than one reason the biggest wins
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Label {
+    Data, // loaded into I
+    Jump,
+    Call,
+}
+
+pub struct Disassembly {
+    lines: Vec<Line>,
+    labels: BTreeMap<u16, Label>,
+}
+
+impl Disassembly {
+    // Trace the control flow from 0x200 to tell code from data
+    pub fn new(rom: &[u8]) -> Disassembly {
+        let mut code = BTreeMap::new();
+        let mut labels = BTreeMap::new();
+        let mut pending = vec![START];
+
+        while let Some(addr) = pending.pop() {
+            if addr < START || code.contains_key(&addr) {
+                continue;
+            }
+            let instruction = match decode_at(rom, addr) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            code.insert(addr, instruction);
+
+            let mut label = |addr: u16, kind: Label| {
+                let label = labels.entry(addr).or_insert(kind);
+                *label = (*label).max(kind);
+            };
+            let next = addr.wrapping_add(instruction.size());
+            match instruction {
+                Instruction::Jp { nnn } | Instruction::JpV0 { nnn } => {
+                    label(nnn, Label::Jump);
+                    pending.push(nnn);
+                }
+                Instruction::Call { nnn } => {
+                    label(nnn, Label::Call);
+                    pending.push(nnn);
+                    pending.push(next);
+                }
+                Instruction::Ret | Instruction::Exit | Instruction::Sys { .. } => {}
+                Instruction::SeByte { .. }
+                | Instruction::SneByte { .. }
+                | Instruction::SeReg { .. }
+                | Instruction::SneReg { .. }
+                | Instruction::Skp { .. }
+                | Instruction::Sknp { .. } => {
+                    let skipped = decode_at(rom, next).map_or(2, |skipped| skipped.size());
+                    pending.push(next);
+                    pending.push(next.wrapping_add(skipped));
+                }
+                Instruction::LdI { nnn } => {
+                    label(nnn, Label::Data);
+                    pending.push(next);
+                }
+                Instruction::LdILong { nnnn } => {
+                    label(nnnn, Label::Data);
+                    pending.push(next);
+                }
+                _ => pending.push(next),
+            }
+        }
+
+        Disassembly::layout(rom, code, labels)
+    }
+
+    // Decode every word as an instruction, for ROMs whose code is only reached
+    // through computed jumps
+    pub fn linear(rom: &[u8]) -> Disassembly {
+        let mut code = BTreeMap::new();
+        let mut offset = 0;
+        while offset < rom.len() {
+            let addr = START + offset as u16;
+            // words that aren't instructions are left as data
+            let size = match decode_at(rom, addr) {
+                Some(instruction) => {
+                    code.insert(addr, instruction);
+                    instruction.size()
+                }
+                None => 2,
+            };
+            offset += size as usize;
+        }
+        Disassembly::layout(rom, code, BTreeMap::new())
+    }
+
+    fn layout(
+        rom: &[u8],
+        code: BTreeMap<u16, Instruction>,
+        mut labels: BTreeMap<u16, Label>,
+    ) -> Disassembly {
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < rom.len() {
+            let addr = START + offset as u16;
+            match code.get(&addr) {
+                Some(&instruction) => {
+                    lines.push(Line::Code { addr, instruction });
+                    offset += instruction.size() as usize;
+                }
+                None => {
+                    lines.push(Line::Data {
+                        addr,
+                        byte: rom[offset],
+                    });
+                    offset += 1;
+                }
+            }
+        }
+        // a label in the middle of an instruction or outside the ROM has
+        // nowhere to go, those addresses stay as numbers
+        labels.retain(|addr, _| lines.binary_search_by_key(addr, |line| line.addr()).is_ok());
+        Disassembly { lines, labels }
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    // Name of the label at addr, if anything jumps, calls or points I there
+    pub fn label(&self, addr: u16) -> Option<String> {
+        self.labels.get(&addr).map(|&kind| {
+            let prefix = match kind {
+                Label::Data => "data",
+                Label::Jump => "label",
+                Label::Call => "sub",
+            };
+            format!("{}_{:03X}", prefix, addr)
+        })
+    }
+
+    // Source for chip8-asm, addresses and raw opcodes go in comments
+    pub fn classic(&self) -> String {
+        let mut out = String::new();
+        for line in self.lines.iter() {
+            if let Some(label) = self.label(line.addr()) {
+                writeln!(out, "{}:", label).unwrap();
+            }
+            let (text, comment) = match *line {
+                Line::Code { addr, instruction } => (
+                    self.classic_instruction(instruction),
+                    format!("{:03X}: {}", addr, raw(instruction)),
+                ),
+                Line::Data { addr, byte } => (
+                    format!("DB {:#04X}", byte),
+                    format!("{:03X}: {}", addr, pixels(byte)),
+                ),
+            };
+            writeln!(out, "\t{:<24}; {}", text, comment).unwrap();
+        }
+        out
+    }
+
+    fn classic_instruction(&self, instruction: Instruction) -> String {
+        use self::Instruction::*;
+
+        let addr = |addr: u16| self.label(addr).unwrap_or_else(|| format!("{:#05X}", addr));
+        match instruction {
+            Sys { nnn } => format!("SYS {:#05X}", nnn),
+            Scd { n } => format!("SCD {}", n),
+            Scu { n } => format!("SCU {}", n),
+            Cls => "CLS".to_string(),
+            Ret => "RET".to_string(),
+            Scr => "SCR".to_string(),
+            Scl => "SCL".to_string(),
+            Exit => "EXIT".to_string(),
+            Low => "LOW".to_string(),
+            High => "HIGH".to_string(),
+            Jp { nnn } => format!("JP {}", addr(nnn)),
+            Call { nnn } => format!("CALL {}", addr(nnn)),
+            SeByte { x, nn } => format!("SE V{:X}, {:#04X}", x, nn),
+            SneByte { x, nn } => format!("SNE V{:X}, {:#04X}", x, nn),
+            SeReg { x, y } => format!("SE V{:X}, V{:X}", x, y),
+            SaveRange { x, y } => format!("SAVE V{:X}, V{:X}", x, y),
+            LoadRange { x, y } => format!("LOAD V{:X}, V{:X}", x, y),
+            LdByte { x, nn } => format!("LD V{:X}, {:#04X}", x, nn),
+            AddByte { x, nn } => format!("ADD V{:X}, {:#04X}", x, nn),
+            LdReg { x, y } => format!("LD V{:X}, V{:X}", x, y),
+            Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+            And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+            Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+            AddReg { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+            Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+            Shr { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+            Subn { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+            Shl { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+            SneReg { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+            LdI { nnn } => format!("LD I, {}", addr(nnn)),
+            JpV0 { nnn } => format!("JP V0, {}", addr(nnn)),
+            Rnd { x, nn } => format!("RND V{:X}, {:#04X}", x, nn),
+            Drw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            Skp { x } => format!("SKP V{:X}", x),
+            Sknp { x } => format!("SKNP V{:X}", x),
+            LdILong { nnnn } => match self.label(nnnn) {
+                Some(label) => format!("LD I, LONG {}", label),
+                None => format!("LD I, LONG {:#06X}", nnnn),
+            },
+            Plane { n } => format!("PLANE {}", n),
+            Audio => "AUDIO".to_string(),
+            LdVxDt { x } => format!("LD V{:X}, DT", x),
+            LdVxK { x } => format!("LD V{:X}, K", x),
+            LdDtVx { x } => format!("LD DT, V{:X}", x),
+            LdStVx { x } => format!("LD ST, V{:X}", x),
+            AddIVx { x } => format!("ADD I, V{:X}", x),
+            LdFVx { x } => format!("LD F, V{:X}", x),
+            LdHfVx { x } => format!("LD HF, V{:X}", x),
+            LdBVx { x } => format!("LD B, V{:X}", x),
+            Pitch { x } => format!("PITCH V{:X}", x),
+            LdIVx { x } => format!("LD [I], V{:X}", x),
+            LdVxI { x } => format!("LD V{:X}, [I]", x),
+            LdRVx { x } => format!("LD R, V{:X}", x),
+            LdVxR { x } => format!("LD V{:X}, R", x),
+        }
+    }
+
+    // Octo source, addresses and raw opcodes go in comments
+    pub fn octo(&self) -> String {
+        let mut out = String::new();
+        for line in self.lines.iter() {
+            if let Some(label) = self.label(line.addr()) {
+                writeln!(out, ": {}", label).unwrap();
+            }
+            let (text, comment) = match *line {
+                Line::Code { addr, instruction } => (
+                    self.octo_instruction(instruction),
+                    format!("{:03X}: {}", addr, raw(instruction)),
+                ),
+                Line::Data { addr, byte } => (
+                    format!("{:#04x}", byte),
+                    format!("{:03X}: {}", addr, pixels(byte)),
+                ),
+            };
+            writeln!(out, "\t{:<24}# {}", text, comment).unwrap();
+        }
+        out
+    }
+
+    fn octo_instruction(&self, instruction: Instruction) -> String {
+        use self::Instruction::*;
+
+        let addr = |addr: u16| self.label(addr).unwrap_or_else(|| format!("{:#05x}", addr));
+        match instruction {
+            // Octo has no mnemonic for machine code calls, emit the bytes
+            Sys { nnn } => format!("{:#04x} {:#04x}", nnn >> 8, nnn & 0xFF),
+            Scd { n } => format!("scroll-down {}", n),
+            Scu { n } => format!("scroll-up {}", n),
+            Cls => "clear".to_string(),
+            Ret => "return".to_string(),
+            Scr => "scroll-right".to_string(),
+            Scl => "scroll-left".to_string(),
+            Exit => "exit".to_string(),
+            Low => "lores".to_string(),
+            High => "hires".to_string(),
+            Jp { nnn } => format!("jump {}", addr(nnn)),
+            Call { nnn } => match self.label(nnn) {
+                Some(label) => label,
+                None => format!(":call {:#05x}", nnn),
+            },
+            // the skips become ifs on the opposite condition
+            SeByte { x, nn } => format!("if v{:x} != {:#04x} then", x, nn),
+            SneByte { x, nn } => format!("if v{:x} == {:#04x} then", x, nn),
+            SeReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+            SneReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+            Skp { x } => format!("if v{:x} -key then", x),
+            Sknp { x } => format!("if v{:x} key then", x),
+            SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+            LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+            LdByte { x, nn } => format!("v{:x} := {:#04x}", x, nn),
+            AddByte { x, nn } => format!("v{:x} += {:#04x}", x, nn),
+            LdReg { x, y } => format!("v{:x} := v{:x}", x, y),
+            Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+            And { x, y } => format!("v{:x} &= v{:x}", x, y),
+            Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+            AddReg { x, y } => format!("v{:x} += v{:x}", x, y),
+            Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+            Shr { x, y } => format!("v{:x} >>= v{:x}", x, y),
+            Subn { x, y } => format!("v{:x} =- v{:x}", x, y),
+            Shl { x, y } => format!("v{:x} <<= v{:x}", x, y),
+            LdI { nnn } => format!("i := {}", addr(nnn)),
+            JpV0 { nnn } => format!("jump0 {}", addr(nnn)),
+            Rnd { x, nn } => format!("v{:x} := random {:#04x}", x, nn),
+            Drw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+            LdILong { nnnn } => match self.label(nnnn) {
+                Some(label) => format!("i := long {}", label),
+                None => format!("i := long {:#06x}", nnnn),
+            },
+            Plane { n } => format!("plane {}", n),
+            Audio => "audio".to_string(),
+            LdVxDt { x } => format!("v{:x} := delay", x),
+            LdVxK { x } => format!("v{:x} := key", x),
+            LdDtVx { x } => format!("delay := v{:x}", x),
+            LdStVx { x } => format!("buzzer := v{:x}", x),
+            AddIVx { x } => format!("i += v{:x}", x),
+            LdFVx { x } => format!("i := hex v{:x}", x),
+            LdHfVx { x } => format!("i := bighex v{:x}", x),
+            LdBVx { x } => format!("bcd v{:x}", x),
+            Pitch { x } => format!("pitch := v{:x}", x),
+            LdIVx { x } => format!("save v{:x}", x),
+            LdVxI { x } => format!("load v{:x}", x),
+            LdRVx { x } => format!("saveflags v{:x}", x),
+            LdVxR { x } => format!("loadflags v{:x}", x),
+        }
+    }
+}
+
+impl Line {
+    pub fn addr(&self) -> u16 {
+        match *self {
+            Line::Code { addr, .. } | Line::Data { addr, .. } => addr,
+        }
+    }
+}
+
+fn decode_at(rom: &[u8], addr: u16) -> Option<Instruction> {
+    let word = |addr: u16| {
+        let offset = addr.checked_sub(START)? as usize;
+        let bytes = rom.get(offset..offset + 2)?;
+        Some(u16::from(bytes[0]) << 8 | u16::from(bytes[1]))
+    };
+    let opcode = word(addr)?;
+    if opcode == 0xF000 {
+        Instruction::decode_pair(opcode, word(addr.wrapping_add(2))?)
+    } else {
+        Instruction::decode(opcode)
+    }
+}
+
+// The opcode as it is in memory
+fn raw(instruction: Instruction) -> String {
+    match instruction {
+        Instruction::LdILong { nnnn } => format!("{:04X} {:04X}", instruction.encode(), nnnn),
+        _ => format!("{:04X}", instruction.encode()),
+    }
+}
+
+// A data byte drawn as a sprite row
+fn pixels(byte: u8) -> String {
+    (0..8)
+        .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+        .collect()
+}
@@ -0,0 +1,121 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
+
+// Everything that can stop the interpreter in the middle of a program.
+// When cpu_tick fails the program counter is left pointing at the
+// instruction that caused the error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    // The opcode at pc doesn't belong to the instruction set
+    UnknownOpcode { pc: u16, opcode: u16 },
+    // 0NNN, a call into a native COSMAC VIP routine we can't run
+    MachineCodeRoutine { pc: u16, addr: u16 },
+    // CALL with all 16 stack slots in use
+    StackOverflow { pc: u16 },
+    // RET with an empty stack
+    StackUnderflow { pc: u16 },
+    // A read or write past the end of the interpreter memory
+    MemoryOutOfBounds { pc: u16, addr: usize },
+    // EX9E/EXA1 with a value in VX that is not a key of the hex keypad
+    InvalidKey { pc: u16, key: u8 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuError::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {:04X} at {:03X}", opcode, pc)
+            }
+            CpuError::MachineCodeRoutine { pc, addr } => write!(
+                f,
+                "machine code routine call to {:03X} at {:03X} is not supported",
+                addr, pc
+            ),
+            CpuError::StackOverflow { pc } => write!(f, "stack overflow at {:03X}", pc),
+            CpuError::StackUnderflow { pc } => write!(f, "stack underflow at {:03X}", pc),
+            CpuError::MemoryOutOfBounds { pc, addr } => write!(
+                f,
+                "memory access out of bounds at {:03X} (address {:X})",
+                pc, addr
+            ),
+            CpuError::InvalidKey { pc, key } => {
+                write!(f, "invalid key {:X} at {:03X}", key, pc)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for CpuError {}
+
+// Why a save state couldn't be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    // Not a save state at all
+    BadMagic,
+    // Saved by an incompatible version of the interpreter
+    UnsupportedVersion(u16),
+    // Saved while running another ROM
+    WrongRom,
+    // Truncated or with impossible values
+    Corrupted,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::WrongRom => write!(f, "save state belongs to another ROM"),
+            StateError::Corrupted => write!(f, "corrupted save state"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for StateError {}
+
+// Why a ROM couldn't be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomError {
+    // Doesn't fit in memory after 0x200, see MAX_ROM_SIZE
+    TooLarge { size: usize },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::TooLarge { size } => write!(
+                f,
+                "ROM is too large ({} bytes, at most {})",
+                size,
+                super::MAX_ROM_SIZE
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for RomError {}
+
+// Why a source file couldn't be assembled, line counts from 1
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for AsmError {}
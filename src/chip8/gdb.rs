@@ -0,0 +1,521 @@
+// A stub for the GDB remote serial protocol, so gdb and other tools that
+// speak it can debug a running program over TCP
+//
+// The registers are v0-vf, i, pc, sp, dt and st, described to the client by
+// target.xml and sent big endian like everything else on the CHIP-8. Software
+// and hardware breakpoints both become Breakpoint::Pc and watchpoints become
+// Breakpoint::Memory. While continuing the machine runs at 60 frames a second
+// so the timers keep real time, and a ^C from the client stops it
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Access, Breakpoint, Cpu, CpuError, Random, StepOutcome};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+// Numbered like in target.xml
+const I: usize = 16;
+const PC: usize = 17;
+const SP: usize = 18;
+const DT: usize = 19;
+const ST: usize = 20;
+const REGISTERS: usize = 21;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+const FRAME: Duration = Duration::from_micros(16_667);
+
+// Why the program stopped, sent back for ?, c and s
+enum Stop {
+    Signal(u8),
+    Watch(Access, u16),
+    Exited,
+}
+
+struct Session<'a, R: Random + 'a> {
+    chip: &'a mut Cpu<R>,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    // false after QStartNoAckMode
+    ack: bool,
+    cycles_per_frame: u32,
+    // instructions run since the timers last ticked
+    cycles: u32,
+    frames: u64,
+    last_stop: String,
+}
+
+// Serves one gdb connection until it detaches, kills the program or hangs up.
+// The timers tick every cycles_per_frame instructions
+pub fn serve_gdb<R: Random>(
+    chip: &mut Cpu<R>,
+    stream: TcpStream,
+    cycles_per_frame: u32,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut session = Session {
+        chip,
+        writer: stream.try_clone()?,
+        reader: BufReader::new(stream),
+        ack: true,
+        cycles_per_frame: cycles_per_frame.max(1),
+        cycles: 0,
+        frames: 0,
+        last_stop: format!("S{:02x}", SIGTRAP),
+    };
+    while let Some(packet) = session.read_packet()? {
+        let packet = String::from_utf8_lossy(&packet).into_owned();
+        match session.handle(&packet)? {
+            Some(reply) => session.send(&reply)?,
+            None => break,
+        }
+        if packet == "QStartNoAckMode" {
+            session.ack = false;
+        }
+    }
+    Ok(())
+}
+
+impl<'a, R: Random> Session<'a, R> {
+    // The reply to a packet, None to end the session
+    fn handle(&mut self, packet: &str) -> io::Result<Option<String>> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => self.last_stop.clone(),
+            "g" => (0..REGISTERS).map(|n| self.register(n).unwrap()).collect(),
+            "G" => ok(self.set_registers(args)),
+            "p" => parse_hex(args)
+                .and_then(|n| self.register(n as usize))
+                .unwrap_or_else(error),
+            "P" => ok(self.set_register(args)),
+            "m" => self.read_memory(args).unwrap_or_else(error),
+            "M" => ok(self.write_memory(args)),
+            "c" | "s" => {
+                if !args.is_empty() {
+                    match parse_hex(args) {
+                        Some(addr) => self.chip.pc = addr as u16,
+                        None => return Ok(Some(error())),
+                    }
+                }
+                let stop = if command == "c" {
+                    self.resume()?
+                } else {
+                    self.step()?
+                };
+                self.last_stop = match stop {
+                    Stop::Signal(signal) => format!("S{:02x}", signal),
+                    Stop::Watch(access, addr) => {
+                        let kind = match access {
+                            Access::Write => "watch",
+                            Access::Read => "rwatch",
+                            Access::ReadWrite => "awatch",
+                        };
+                        format!("T{:02x}{}:{:x};", SIGTRAP, kind, addr)
+                    }
+                    Stop::Exited => "W00".to_string(),
+                };
+                self.last_stop.clone()
+            }
+            "Z" | "z" => match self.breakpoint(command == "Z", args) {
+                Some(done) => ok(done),
+                None => String::new(),
+            },
+            "q" | "Q" => self.query(packet),
+            // there's only the one thread
+            "H" | "T" => "OK".to_string(),
+            "D" => {
+                self.send("OK")?;
+                return Ok(None);
+            }
+            "k" => return Ok(None),
+            // an empty reply tells the client it's not supported
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let mut parts = range.splitn(2, ',');
+            let offset = parts.next().and_then(parse_hex);
+            let len = parts.next().and_then(parse_hex);
+            match (offset, len) {
+                (Some(offset), Some(len)) => {
+                    let start = (offset as usize).min(TARGET_XML.len());
+                    let end = (start + len as usize).min(TARGET_XML.len());
+                    let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+                    format!("{}{}", more, &TARGET_XML[start..end])
+                }
+                _ => error(),
+            }
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "QStartNoAckMode" {
+            "OK".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    // The register as big endian hex
+    fn register(&self, n: usize) -> Option<String> {
+        let chip = &self.chip;
+        Some(match n {
+            0..=15 => format!("{:02x}", chip.v[n]),
+            I => format!("{:04x}", chip.i),
+            PC => format!("{:04x}", chip.pc),
+            SP => format!("{:02x}", chip.sp),
+            DT => format!("{:02x}", chip.delay_timer),
+            ST => format!("{:02x}", chip.sound_timer),
+            _ => return None,
+        })
+    }
+
+    fn write_register(&mut self, n: usize, value: u16) {
+        let chip = &mut self.chip;
+        match n {
+            0..=15 => chip.v[n] = value as u8,
+            I => chip.i = value,
+            PC => chip.pc = value,
+            SP => chip.sp = (value as u8).min(chip.stack.len() as u8),
+            DT => chip.delay_timer = value as u8,
+            ST => chip.sound_timer = value as u8,
+            _ => {}
+        }
+    }
+
+    // G with all the registers in one go, like g sends them
+    fn set_registers(&mut self, hex: &str) -> bool {
+        let mut rest = hex;
+        for n in 0..REGISTERS {
+            let digits = if n == I || n == PC { 4 } else { 2 };
+            if rest.len() < digits {
+                return false;
+            }
+            let (value, next) = rest.split_at(digits);
+            match parse_hex(value) {
+                Some(value) => self.write_register(n, value as u16),
+                None => return false,
+            }
+            rest = next;
+        }
+        true
+    }
+
+    // P n=value
+    fn set_register(&mut self, args: &str) -> bool {
+        let mut parts = args.splitn(2, '=');
+        let n = parts.next().and_then(parse_hex);
+        let value = parts.next().and_then(parse_hex);
+        match (n, value) {
+            (Some(n), Some(value)) if (n as usize) < REGISTERS => {
+                self.write_register(n as usize, value as u16);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // m addr,len, cut short at the end of memory
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let mut parts = args.splitn(2, ',');
+        let addr = parts.next().and_then(parse_hex)? as usize;
+        let len = parts.next().and_then(parse_hex)? as usize;
+        let memory = &self.chip.memory;
+        if addr >= memory.len() {
+            return None;
+        }
+        let end = (addr + len).min(memory.len());
+        Some(to_hex(&memory[addr..end]))
+    }
+
+    // M addr,len:bytes
+    fn write_memory(&mut self, args: &str) -> bool {
+        let mut parts = args.splitn(2, ':');
+        let mut range = parts.next().unwrap_or("").splitn(2, ',');
+        let addr = range.next().and_then(parse_hex);
+        let len = range.next().and_then(parse_hex);
+        let bytes = parts.next().and_then(parse_bytes);
+        match (addr, len, bytes) {
+            (Some(addr), Some(len), Some(bytes))
+                if bytes.len() == len as usize
+                    && addr as usize + bytes.len() <= self.chip.memory.len() =>
+            {
+                self.chip.memory[addr as usize..addr as usize + bytes.len()]
+                    .copy_from_slice(&bytes);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Z/z type,addr,kind, None for the types there's no breakpoint for
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Option<bool> {
+        let mut parts = args.split(',');
+        let kind = parts.next()?;
+        let addr = parts.next().and_then(parse_hex)? as u16;
+        let len = parts.next().and_then(parse_hex).unwrap_or(1).max(1) as u16;
+        let watch = |access| Breakpoint::Memory {
+            start: addr,
+            end: addr.saturating_add(len - 1),
+            access,
+        };
+        let breakpoint = match kind {
+            "0" | "1" => Breakpoint::Pc(addr),
+            "2" => watch(Access::Write),
+            "3" => watch(Access::Read),
+            "4" => watch(Access::ReadWrite),
+            _ => return None,
+        };
+        let existing = self
+            .chip
+            .breakpoints()
+            .find(|&(_, other)| other == breakpoint)
+            .map(|(id, _)| id);
+        Some(match (insert, existing) {
+            (true, Some(_)) => true,
+            (true, None) => self.chip.add_breakpoint(breakpoint).is_some(),
+            (false, Some(id)) => self.chip.remove_breakpoint(id),
+            (false, None) => false,
+        })
+    }
+
+    // Runs one instruction and ticks the timers at the end of a frame. A
+    // display_wait doesn't wait for real time, the frame just ends
+    fn tick(&mut self) -> Result<StepOutcome, CpuError> {
+        let outcome = self.chip.cpu_tick();
+        self.cycles += 1;
+        if outcome == Ok(StepOutcome::WaitingForVblank) || self.cycles >= self.cycles_per_frame {
+            self.chip.timers_tick();
+            self.cycles = 0;
+            self.frames += 1;
+        }
+        outcome
+    }
+
+    fn step(&mut self) -> io::Result<Stop> {
+        let mut outcome = self.tick();
+        // gdb wants the instruction run even with a breakpoint on it
+        if let Ok(StepOutcome::Break(hit)) = outcome {
+            if hit.breakpoint.before() {
+                outcome = self.tick();
+            }
+        }
+        if outcome == Ok(StepOutcome::WaitingForVblank) {
+            outcome = self.tick();
+        }
+        self.stop(outcome)
+    }
+
+    fn resume(&mut self) -> io::Result<Stop> {
+        let mut frames = self.frames;
+        let mut deadline = Instant::now() + FRAME;
+        loop {
+            match self.tick() {
+                Ok(StepOutcome::Executed)
+                | Ok(StepOutcome::WaitingForKey)
+                | Ok(StepOutcome::WaitingForVblank) => {}
+                outcome => return self.stop(outcome),
+            }
+            if self.frames != frames {
+                frames = self.frames;
+                if self.interrupted()? {
+                    return Ok(Stop::Signal(SIGINT));
+                }
+                let now = Instant::now();
+                if deadline > now {
+                    thread::sleep(deadline - now);
+                    deadline += FRAME;
+                } else {
+                    deadline = now + FRAME;
+                }
+            }
+        }
+    }
+
+    // The stop for how the last instruction went, errors are printed on the
+    // gdb console too
+    fn stop(&mut self, outcome: Result<StepOutcome, CpuError>) -> io::Result<Stop> {
+        Ok(match outcome {
+            Ok(StepOutcome::Exited) => Stop::Exited,
+            Ok(StepOutcome::Break(hit)) => match hit.breakpoint {
+                Breakpoint::Memory { start, access, .. } => {
+                    Stop::Watch(access, hit.addr.unwrap_or(start))
+                }
+                _ => Stop::Signal(SIGTRAP),
+            },
+            Ok(_) => Stop::Signal(SIGTRAP),
+            Err(err) => {
+                let message = format!("{}\n", err);
+                self.send(&format!("O{}", to_hex(message.as_bytes())))?;
+                match err {
+                    CpuError::UnknownOpcode { .. }
+                    | CpuError::MachineCodeRoutine { .. }
+                    | CpuError::InvalidKey { .. } => Stop::Signal(SIGILL),
+                    _ => Stop::Signal(SIGSEGV),
+                }
+            }
+        })
+    }
+
+    // Whether the client sent a ^C while the program was running, or hung up
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.writer.set_nonblocking(true)?;
+        let buffered = self.reader.fill_buf().map(|buf| {
+            // acks for the last reply can come first
+            let acks = buf.iter().take_while(|&&b| b == b'+').count();
+            (acks, buf.is_empty(), buf.get(acks) == Some(&0x03))
+        });
+        self.writer.set_nonblocking(false)?;
+        match buffered {
+            Ok((acks, hung_up, interrupt)) => {
+                self.reader.consume(acks + interrupt as usize);
+                Ok(hung_up || interrupt)
+            }
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    // The next $packet#checksum without the framing, None when the client
+    // hung up
+    fn read_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            // acks, naks and ^C while stopped are skipped
+            loop {
+                match self.read_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => {}
+                    None => return Ok(None),
+                }
+            }
+            let mut packet = Vec::new();
+            let mut sum = 0u8;
+            let mut escaped = false;
+            loop {
+                let byte = match self.read_byte()? {
+                    Some(byte) => byte,
+                    None => return Ok(None),
+                };
+                if byte == b'#' && !escaped {
+                    break;
+                }
+                sum = sum.wrapping_add(byte);
+                if escaped {
+                    packet.push(byte ^ 0x20);
+                    escaped = false;
+                } else if byte == b'}' {
+                    escaped = true;
+                } else {
+                    packet.push(byte);
+                }
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                == Some(sum);
+            if !self.ack {
+                return Ok(Some(packet));
+            }
+            if valid {
+                self.writer.write_all(b"+")?;
+                return Ok(Some(packet));
+            }
+            self.writer.write_all(b"-")?;
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        loop {
+            match self.reader.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        for &byte in data.as_bytes() {
+            if let b'$' | b'#' | b'}' | b'*' = byte {
+                packet.push(b'}');
+                packet.push(byte ^ 0x20);
+            } else {
+                packet.push(byte);
+            }
+        }
+        let sum = packet[1..].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        packet.extend(format!("#{:02x}", sum).bytes());
+        self.writer.write_all(&packet)
+    }
+}
+
+fn ok(done: bool) -> String {
+    if done {
+        "OK".to_string()
+    } else {
+        error()
+    }
+}
+
+fn error() -> String {
+    "E01".to_string()
+}
+
+fn parse_hex(hex: &str) -> Option<u32> {
+    u32::from_str_radix(hex, 16).ok()
+}
+
+fn parse_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|n| u8::from_str_radix(hex.get(n..n + 2)?, 16).ok())
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
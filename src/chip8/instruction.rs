@@ -0,0 +1,283 @@
+use core::fmt;
+
+// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction
+// x and y are register numbers, nn is a byte, n a nibble and nnn an address
+// LdILong is the only one taking 4 bytes, its address follows the F000 opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Sys { nnn: u16 },            // 0NNN
+    Scd { n: u8 },               // 00CN
+    Scu { n: u8 },               // 00DN
+    Cls,                         // 00E0
+    Ret,                         // 00EE
+    Scr,                         // 00FB
+    Scl,                         // 00FC
+    Exit,                        // 00FD
+    Low,                         // 00FE
+    High,                        // 00FF
+    Jp { nnn: u16 },             // 1NNN
+    Call { nnn: u16 },           // 2NNN
+    SeByte { x: u8, nn: u8 },    // 3XNN
+    SneByte { x: u8, nn: u8 },   // 4XNN
+    SeReg { x: u8, y: u8 },      // 5XY0
+    SaveRange { x: u8, y: u8 },  // 5XY2
+    LoadRange { x: u8, y: u8 },  // 5XY3
+    LdByte { x: u8, nn: u8 },    // 6XNN
+    AddByte { x: u8, nn: u8 },   // 7XNN
+    LdReg { x: u8, y: u8 },      // 8XY0
+    Or { x: u8, y: u8 },         // 8XY1
+    And { x: u8, y: u8 },        // 8XY2
+    Xor { x: u8, y: u8 },        // 8XY3
+    AddReg { x: u8, y: u8 },     // 8XY4
+    Sub { x: u8, y: u8 },        // 8XY5
+    Shr { x: u8, y: u8 },        // 8XY6
+    Subn { x: u8, y: u8 },       // 8XY7
+    Shl { x: u8, y: u8 },        // 8XYE
+    SneReg { x: u8, y: u8 },     // 9XY0
+    LdI { nnn: u16 },            // ANNN
+    JpV0 { nnn: u16 },           // BNNN
+    Rnd { x: u8, nn: u8 },       // CXNN
+    Drw { x: u8, y: u8, n: u8 }, // DXYN
+    Skp { x: u8 },               // EX9E
+    Sknp { x: u8 },              // EXA1
+    LdILong { nnnn: u16 },       // F000 NNNN
+    Plane { n: u8 },             // FN01
+    Audio,                       // F002
+    LdVxDt { x: u8 },            // FX07
+    LdVxK { x: u8 },             // FX0A
+    LdDtVx { x: u8 },            // FX15
+    LdStVx { x: u8 },            // FX18
+    AddIVx { x: u8 },            // FX1E
+    LdFVx { x: u8 },             // FX29
+    LdHfVx { x: u8 },            // FX30
+    LdBVx { x: u8 },             // FX33
+    Pitch { x: u8 },             // FX3A
+    LdIVx { x: u8 },             // FX55
+    LdVxI { x: u8 },             // FX65
+    LdRVx { x: u8 },             // FX75
+    LdVxR { x: u8 },             // FX85
+}
+
+impl Instruction {
+    // Returns None if the opcode is not part of the instruction set
+    // F000 needs the word after it, use decode_pair for it
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        use self::Instruction::*;
+
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        let instruction = match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00C0..=0x00CF => Scd { n },
+                0x00D0..=0x00DF => Scu { n },
+                0x00E0 => Cls,
+                0x00EE => Ret,
+                0x00FB => Scr,
+                0x00FC => Scl,
+                0x00FD => Exit,
+                0x00FE => Low,
+                0x00FF => High,
+                _ => Sys { nnn },
+            },
+            0x1000 => Jp { nnn },
+            0x2000 => Call { nnn },
+            0x3000 => SeByte { x, nn },
+            0x4000 => SneByte { x, nn },
+            0x5000 => match n {
+                0x0 => SeReg { x, y },
+                0x2 => SaveRange { x, y },
+                0x3 => LoadRange { x, y },
+                _ => return None,
+            },
+            0x6000 => LdByte { x, nn },
+            0x7000 => AddByte { x, nn },
+            0x8000 => match n {
+                0x0 => LdReg { x, y },
+                0x1 => Or { x, y },
+                0x2 => And { x, y },
+                0x3 => Xor { x, y },
+                0x4 => AddReg { x, y },
+                0x5 => Sub { x, y },
+                0x6 => Shr { x, y },
+                0x7 => Subn { x, y },
+                0xE => Shl { x, y },
+                _ => return None,
+            },
+            0x9000 if n == 0 => SneReg { x, y },
+            0xA000 => LdI { nnn },
+            0xB000 => JpV0 { nnn },
+            0xC000 => Rnd { x, nn },
+            0xD000 => Drw { x, y, n },
+            0xE000 => match nn {
+                0x9E => Skp { x },
+                0xA1 => Sknp { x },
+                _ => return None,
+            },
+            0xF000 => match nn {
+                0x01 => Plane { n: x },
+                0x02 if x == 0 => Audio,
+                0x07 => LdVxDt { x },
+                0x0A => LdVxK { x },
+                0x15 => LdDtVx { x },
+                0x18 => LdStVx { x },
+                0x1E => AddIVx { x },
+                0x29 => LdFVx { x },
+                0x30 => LdHfVx { x },
+                0x33 => LdBVx { x },
+                0x3A => Pitch { x },
+                0x55 => LdIVx { x },
+                0x65 => LdVxI { x },
+                0x75 => LdRVx { x },
+                0x85 => LdVxR { x },
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(instruction)
+    }
+
+    // Decode an opcode together with the word that follows it in memory,
+    // which is only part of the instruction for F000 NNNN
+    pub fn decode_pair(opcode: u16, next: u16) -> Option<Instruction> {
+        if opcode == 0xF000 {
+            return Some(Instruction::LdILong { nnnn: next });
+        }
+        Instruction::decode(opcode)
+    }
+
+    // Length in bytes
+    pub fn size(&self) -> u16 {
+        match *self {
+            Instruction::LdILong { .. } => 4,
+            _ => 2,
+        }
+    }
+
+    // The opcode this instruction decodes from
+    // For LdILong that's only the first word, nnnn goes right after it
+    pub fn encode(&self) -> u16 {
+        use self::Instruction::*;
+
+        let xy = |base: u16, x: u8, y: u8| base | u16::from(x) << 8 | u16::from(y) << 4;
+        let xnn = |base: u16, x: u8, nn: u8| base | u16::from(x) << 8 | u16::from(nn);
+
+        match *self {
+            Sys { nnn } => nnn & 0x0FFF,
+            Scd { n } => 0x00C0 | u16::from(n & 0xF),
+            Scu { n } => 0x00D0 | u16::from(n & 0xF),
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            Scr => 0x00FB,
+            Scl => 0x00FC,
+            Exit => 0x00FD,
+            Low => 0x00FE,
+            High => 0x00FF,
+            Jp { nnn } => 0x1000 | (nnn & 0x0FFF),
+            Call { nnn } => 0x2000 | (nnn & 0x0FFF),
+            SeByte { x, nn } => xnn(0x3000, x, nn),
+            SneByte { x, nn } => xnn(0x4000, x, nn),
+            SeReg { x, y } => xy(0x5000, x, y),
+            SaveRange { x, y } => xy(0x5002, x, y),
+            LoadRange { x, y } => xy(0x5003, x, y),
+            LdByte { x, nn } => xnn(0x6000, x, nn),
+            AddByte { x, nn } => xnn(0x7000, x, nn),
+            LdReg { x, y } => xy(0x8000, x, y),
+            Or { x, y } => xy(0x8001, x, y),
+            And { x, y } => xy(0x8002, x, y),
+            Xor { x, y } => xy(0x8003, x, y),
+            AddReg { x, y } => xy(0x8004, x, y),
+            Sub { x, y } => xy(0x8005, x, y),
+            Shr { x, y } => xy(0x8006, x, y),
+            Subn { x, y } => xy(0x8007, x, y),
+            Shl { x, y } => xy(0x800E, x, y),
+            SneReg { x, y } => xy(0x9000, x, y),
+            LdI { nnn } => 0xA000 | (nnn & 0x0FFF),
+            JpV0 { nnn } => 0xB000 | (nnn & 0x0FFF),
+            Rnd { x, nn } => xnn(0xC000, x, nn),
+            Drw { x, y, n } => xy(0xD000, x, y) | u16::from(n & 0xF),
+            Skp { x } => xnn(0xE09E, x, 0),
+            Sknp { x } => xnn(0xE0A1, x, 0),
+            LdILong { .. } => 0xF000,
+            Plane { n } => xnn(0xF001, n, 0),
+            Audio => 0xF002,
+            LdVxDt { x } => xnn(0xF007, x, 0),
+            LdVxK { x } => xnn(0xF00A, x, 0),
+            LdDtVx { x } => xnn(0xF015, x, 0),
+            LdStVx { x } => xnn(0xF018, x, 0),
+            AddIVx { x } => xnn(0xF01E, x, 0),
+            LdFVx { x } => xnn(0xF029, x, 0),
+            LdHfVx { x } => xnn(0xF030, x, 0),
+            LdBVx { x } => xnn(0xF033, x, 0),
+            Pitch { x } => xnn(0xF03A, x, 0),
+            LdIVx { x } => xnn(0xF055, x, 0),
+            LdVxI { x } => xnn(0xF065, x, 0),
+            LdRVx { x } => xnn(0xF075, x, 0),
+            LdVxR { x } => xnn(0xF085, x, 0),
+        }
+    }
+}
+
+// Classic mnemonics, e.g. "LD V[3], byte(0x1F)"
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Instruction::*;
+
+        match *self {
+            Sys { nnn } => write!(f, "SYS addr({:#05X})", nnn),
+            Scd { n } => write!(f, "SCD nibble({})", n),
+            Scu { n } => write!(f, "SCU nibble({})", n),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Scr => write!(f, "SCR"),
+            Scl => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            Jp { nnn } => write!(f, "JP addr({:#05X})", nnn),
+            Call { nnn } => write!(f, "CALL addr({:#05X})", nnn),
+            SeByte { x, nn } => write!(f, "SE V[{:X}], byte({:#04X})", x, nn),
+            SneByte { x, nn } => write!(f, "SNE V[{:X}], byte({:#04X})", x, nn),
+            SeReg { x, y } => write!(f, "SE V[{:X}], V[{:X}]", x, y),
+            SaveRange { x, y } => write!(f, "SAVE V[{:X}] - V[{:X}]", x, y),
+            LoadRange { x, y } => write!(f, "LOAD V[{:X}] - V[{:X}]", x, y),
+            LdByte { x, nn } => write!(f, "LD V[{:X}], byte({:#04X})", x, nn),
+            AddByte { x, nn } => write!(f, "ADD V[{:X}], byte({:#04X})", x, nn),
+            LdReg { x, y } => write!(f, "LD V[{:X}], V[{:X}]", x, y),
+            Or { x, y } => write!(f, "OR V[{:X}], V[{:X}]", x, y),
+            And { x, y } => write!(f, "AND V[{:X}], V[{:X}]", x, y),
+            Xor { x, y } => write!(f, "XOR V[{:X}], V[{:X}]", x, y),
+            AddReg { x, y } => write!(f, "ADD V[{:X}], V[{:X}]", x, y),
+            Sub { x, y } => write!(f, "SUB V[{:X}], V[{:X}]", x, y),
+            Shr { x, y } => write!(f, "SHR V[{:X}] {{, V[{:X}]}}", x, y),
+            Subn { x, y } => write!(f, "SUBN V[{:X}], V[{:X}]", x, y),
+            Shl { x, y } => write!(f, "SHL V[{:X}] {{, V[{:X}]}}", x, y),
+            SneReg { x, y } => write!(f, "SNE V[{:X}], V[{:X}]", x, y),
+            LdI { nnn } => write!(f, "LD I, addr({:#05X})", nnn),
+            JpV0 { nnn } => write!(f, "JP V[0], addr({:#05X})", nnn),
+            Rnd { x, nn } => write!(f, "RND V[{:X}], byte({:#04X})", x, nn),
+            Drw { x, y, n } => write!(f, "DRW V[{:X}], V[{:X}], nibble({})", x, y, n),
+            Skp { x } => write!(f, "SKP V[{:X}]", x),
+            Sknp { x } => write!(f, "SKNP V[{:X}]", x),
+            LdILong { nnnn } => write!(f, "LD I, long({:#06X})", nnnn),
+            Plane { n } => write!(f, "PLANE nibble({})", n),
+            Audio => write!(f, "AUDIO"),
+            LdVxDt { x } => write!(f, "LD V[{:X}], DT", x),
+            LdVxK { x } => write!(f, "LD V[{:X}], K", x),
+            LdDtVx { x } => write!(f, "LD DT, V[{:X}]", x),
+            LdStVx { x } => write!(f, "LD ST, V[{:X}]", x),
+            AddIVx { x } => write!(f, "ADD I, V[{:X}]", x),
+            LdFVx { x } => write!(f, "LD F, V[{:X}]", x),
+            LdHfVx { x } => write!(f, "LD HF, V[{:X}]", x),
+            LdBVx { x } => write!(f, "LD B, V[{:X}]", x),
+            Pitch { x } => write!(f, "PITCH V[{:X}]", x),
+            LdIVx { x } => write!(f, "LD [I], V[{:X}]", x),
+            LdVxI { x } => write!(f, "LD V[{:X}], [I]", x),
+            LdRVx { x } => write!(f, "LD R, V[{:X}]", x),
+            LdVxR { x } => write!(f, "LD V[{:X}], R", x),
+        }
+    }
+}
@@ -1,42 +1,140 @@
-extern crate log;
-extern crate rand;
-use self::rand::Rng;
-
-pub struct Cpu {
-    memory: [u8; 4096],
-    v: [u8; 16], // registers
-    i: u16,      // address register
-    pc: u16,     // program counter
-    sp: u8,      // stack pointer
+#[cfg(feature = "std")]
+mod asm;
+mod breakpoints;
+#[cfg(feature = "std")]
+mod disasm;
+mod error;
+#[cfg(feature = "std")]
+mod gdb;
+mod instruction;
+mod quirks;
+mod random;
+#[cfg(feature = "std")]
+mod rewind;
+mod scheduler;
+mod state;
+#[cfg(test)]
+mod tests;
+mod timing;
+#[cfg(feature = "std")]
+mod trace;
+
+#[cfg(feature = "std")]
+pub use self::asm::{assemble, assemble_file, assemble_octo, assemble_octo_file, Assembly, Listing};
+pub use self::breakpoints::{
+    Access, Breakpoint, BreakpointHit, Comparison, Condition, Register, MAX_BREAKPOINTS,
+};
+#[cfg(feature = "std")]
+pub use self::disasm::{Disassembly, Line};
+#[cfg(feature = "std")]
+pub use self::error::AsmError;
+pub use self::error::{CpuError, RomError, StateError};
+#[cfg(feature = "std")]
+pub use self::gdb::serve_gdb;
+pub use self::instruction::Instruction;
+pub use self::quirks::{LoadStore, Quirks};
+pub use self::random::{Random, XorShift};
+#[cfg(feature = "std")]
+pub use self::rewind::Rewind;
+pub use self::scheduler::{Scheduler, Speed};
+pub use self::state::STATE_SIZE;
+pub use self::timing::{VIP_FRAME_CYCLES, VIP_INTERRUPT_CYCLES};
+#[cfg(feature = "std")]
+pub use self::trace::TraceRecord;
+
+// The biggest ROM load_rom takes, all of memory after 0x200
+pub const MAX_ROM_SIZE: usize = 0x10000 - 0x200;
+
+// What happened during a successful cpu_tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    // The instruction ran and pc points to the next one
+    Executed,
+    // FX0A is blocking until a key is pressed, pc still points to it
+    WaitingForKey,
+    // A sprite was drawn with the display_wait quirk on, nothing else runs
+    // until the next timers_tick
+    WaitingForVblank,
+    // 00FD asked the interpreter to quit, pc stays on it
+    Exited,
+    // A breakpoint fired, see breakpoints.rs for where pc is left
+    Break(BreakpointHit),
+}
+
+// The small 4x5 font lives at the start of memory, the 8x10 one right after
+// it: the SUPER-CHIP digits and the A-F that XO-CHIP added
+const BIG_FONT_ADDR: u16 = 0x50;
+
+pub struct Cpu<R = XorShift> {
+    memory: [u8; 0x10000], // XO-CHIP programs can use the full 64K
+    v: [u8; 16],           // registers
+    i: u16,                // address register
+    pc: u16,               // program counter
+    opcode_pc: u16,        // address of the instruction being executed
+    sp: u8,                // stack pointer
     stack: [u16; 16],
     pub delay_timer: u8,
     pub sound_timer: u8,
     keyboard: [u8; 16],
-    pub gfx: [u8; 64 * 32],
+    // width() * height() pixels, one byte each, bit 0 is the first XO-CHIP
+    // bitplane and bit 1 the second
+    pub gfx: [u8; 128 * 64],
+    hires: bool,       // SUPER-CHIP 128x64 mode
+    rpl: [u8; 16],     // SUPER-CHIP RPL user flags
+    plane: u8,         // XO-CHIP bitplanes selected for drawing
+    pattern: [u8; 16], // XO-CHIP audio pattern buffer
+    pattern_set: bool, // F002 ran at least once
+    pitch: u8,         // XO-CHIP audio pitch
     pub key_pressed: bool,
+    // set when the screen changes, the frontend clears it once it's shown
     pub draw_flag: bool,
     pub sound_flag: bool,
-    load_store_quirk: bool,
-    shift_quirk: bool,
+    pub quirks: Quirks,
+    vblank_wait: bool,
+    random: R,     // feeds CXNN
+    rom_hash: u64, // identifies the loaded ROM in save states
+    cycles: u64,   // VIP machine cycles run, see timing.rs
+    breakpoints: breakpoints::Breakpoints,
+    #[cfg(feature = "std")]
+    trace: Option<trace::Trace>,
 }
 
-pub fn new() -> Cpu {
+// A Cpu with the random source seeded with XorShift::DEFAULT_SEED, use
+// with_random to pick another seed or source
+pub fn new(quirks: Quirks) -> Cpu {
+    with_random(quirks, XorShift::default())
+}
+
+pub fn with_random<R: Random>(quirks: Quirks, random: R) -> Cpu<R> {
     let mut chip8 = Cpu {
-        memory: [0; 4096],
+        memory: [0; 0x10000],
         v: [0; 16],
         i: 0,
         pc: 0x200, // chip 8 programs start at position 512
+        opcode_pc: 0x200,
         sp: 0,
         stack: [0; 16],
         delay_timer: 0,
         sound_timer: 0,
         keyboard: [0; 16],
-        gfx: [0; 64 * 32],
+        gfx: [0; 128 * 64],
+        hires: false,
+        rpl: [0; 16],
+        plane: 1,
+        pattern: [0; 16],
+        pattern_set: false,
+        pitch: 64,
         key_pressed: false,
         draw_flag: false,
         sound_flag: false,
-        load_store_quirk: false,
-        shift_quirk: false,
+        quirks,
+        vblank_wait: false,
+        random,
+        rom_hash: state::rom_hash(&[]),
+        cycles: 0,
+        breakpoints: breakpoints::Breakpoints::new(),
+        #[cfg(feature = "std")]
+        trace: None,
     };
     let chip8_fontset = [
             0xF0, 0x90, 0x90, 0x90, 0xF0, //0
@@ -56,206 +154,268 @@ pub fn new() -> Cpu {
             0xF0, 0x80, 0xF0, 0x80, 0xF0, //E
             0xF0, 0x80, 0xF0, 0x80, 0x80]; //F
 
+    let schip_fontset = [
+        0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, //0
+        0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, //1
+        0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, //2
+        0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, //3
+        0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, //4
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, //5
+        0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, //6
+        0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, //7
+        0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, //8
+        0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, //9
+        0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, //A
+        0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, //B
+        0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, //C
+        0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, //D
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, //E
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, //F
+    ];
+
     // load fontset
     chip8.memory[..chip8_fontset.len()].clone_from_slice(&chip8_fontset[..]);
+    let big_font = BIG_FONT_ADDR as usize..BIG_FONT_ADDR as usize + schip_fontset.len();
+    chip8.memory[big_font].clone_from_slice(&schip_fontset[..]);
     chip8
 }
 
-impl Cpu {
-    pub fn load_rom(&mut self, rom: &[u8]) {
-        self.memory[512..(rom.len() + 512)].clone_from_slice(&rom[..]);
+impl<R: Random> Cpu<R> {
+    // Copy the ROM to 0x200, where programs start. Memory is left as it was
+    // when the ROM doesn't fit
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), RomError> {
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(RomError::TooLarge { size: rom.len() });
+        }
+        self.memory[512..(rom.len() + 512)].clone_from_slice(rom);
+        self.rom_hash = state::rom_hash(rom);
+        Ok(())
     }
 
-    pub fn cpu_tick(&mut self) {
-        let opcode = (u16::from(self.memory[self.pc as usize]) << 8)
-            | u16::from(self.memory[self.pc as usize + 1]);
+    // Size of the display in the current mode, gfx holds width() * height() pixels
+    pub fn width(&self) -> usize {
+        if self.hires {
+            128
+        } else {
+            64
+        }
+    }
 
-        self.pc += 2; // go to the next instruction
+    pub fn height(&self) -> usize {
+        if self.hires {
+            64
+        } else {
+            32
+        }
+    }
 
-        // debug!("PC: {}", self.pc);
+    // The XO-CHIP audio pattern buffer, None until a program loads one with F002
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        if self.pattern_set {
+            Some(&self.pattern)
+        } else {
+            None
+        }
+    }
 
-        self.draw_flag = false;
+    // XO-CHIP audio pitch, 64 plays the pattern at 4000 bits per second
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
 
-        if self.sound_timer > 0 {
-            self.sound_flag = true;
-        } else {
-            self.sound_flag = false;
-        }
-
-        let x = (opcode & 0x0F00) >> 8;
-        let y = (opcode & 0x00F0) >> 4;
-        let n = opcode & 0x000F;
-        let nn = opcode & 0x00FF;
-        let nnn = opcode & 0x0FFF;
-
-        match opcode & 0xF000 {
-            0x0000 => match opcode & 0x00FF {
-                0x00 => self.op_0nnn(nnn),
-                0xE0 => self.op_00e0(),
-                0xEE => self.op_00ee(),
-                _ => panic!("unknown opcode {:X}", opcode),
-            },
-            0x1000 => self.op_1nnn(nnn),
-            0x2000 => self.op_2nnn(nnn),
-            0x3000 => self.op_3xnn(x, nn),
-            0x4000 => self.op_4xnn(x, nn),
-            0x5000 => self.op_5xy0(x, y),
-            0x6000 => self.op_6xnn(x, nn),
-            0x7000 => self.op_7xnn(x, nn),
-            0x8000 => match opcode & 0x000F {
-                0x0 => self.op_8xy0(x, y),
-                0x1 => self.op_8xy1(x, y),
-                0x2 => self.op_8xy2(x, y),
-                0x3 => self.op_8xy3(x, y),
-                0x04 => self.op_8xy4(x, y),
-                0x05 => self.op_8xy5(x, y),
-                0x06 => self.op_8xy6(x, y),
-                0x07 => self.op_8xy7(x, y),
-                0x0E => self.op_8xye(x, y),
-                _ => panic!("unknown opcode {:X}", opcode),
-            },
-            //9XY0 Skip the following instruction if the value of register VX
-            //is not equal to the value of register VY
-            0x9000 => {
-                debug!("SNE V[{}], V[{}]", x, y);
-                if self.v[x as usize] != self.v[y as usize] {
-                    self.pc += 2;
-                }
-            }
-            //ANNN Store memory address NNN in register I
-            0xA000 => {
-                debug!("LD I, addr({})", nnn);
-                self.i = nnn;
-            }
-            //BNNN Jump to address NNN + V0
-            0xB000 => {
-                debug!("JP V[0x0], addr({})", nnn);
-                self.pc = nnn + u16::from(self.v[0x0]);
+    // Registers, read only so frontends and tools can't break invariants
+    pub fn v(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    // Return addresses currently on the stack, the innermost call last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    // The whole address space, for debuggers and disassemblers
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn is_key_down(&self, key: u8) -> bool {
+        self.keyboard[key as usize] != 0
+    }
+
+    // Playback rate of the audio pattern in bits per second
+    #[cfg(feature = "std")]
+    pub fn pattern_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((f32::from(self.pitch) - 64.0) / 48.0)
+    }
+
+    // Run a single instruction
+    // On error pc is moved back to the faulting instruction so the caller can
+    // inspect the machine exactly as it was when things went wrong
+    pub fn cpu_tick(&mut self) -> Result<StepOutcome, CpuError> {
+        if self.vblank_wait {
+            return Ok(StepOutcome::WaitingForVblank);
+        }
+        let pc = self.pc;
+        let result = self.fetch().and_then(|instruction| {
+            let cycles = self.vip_cycles(instruction);
+            #[cfg(feature = "std")]
+            let outcome = if self.trace.is_some() {
+                self.execute_traced(instruction)?
+            } else {
+                self.execute_checked(instruction)?
+            };
+            #[cfg(not(feature = "std"))]
+            let outcome = self.execute_checked(instruction)?;
+            // a breakpoint before the instruction means it didn't run
+            if !matches!(outcome, StepOutcome::Break(hit) if hit.breakpoint.before()) {
+                self.cycles += u64::from(cycles);
             }
-            //CXNN Set VX to a random number with a mask of NN
-            0xC000 => {
-                debug!("RND V[{}], byte({})", x, nn);
-                self.v[x as usize] = rand::thread_rng().gen::<u8>() & nn as u8;
+            Ok(outcome)
+        });
+        if result.is_err() {
+            self.pc = pc;
+        }
+        result
+    }
+
+    // Read and decode the instruction at pc and move pc to the next one
+    fn fetch(&mut self) -> Result<Instruction, CpuError> {
+        self.opcode_pc = self.pc;
+        let opcode = self.read_word(self.pc as usize)?;
+
+        let decoded = if opcode == 0xF000 {
+            let next = self.read_word(self.pc as usize + 2)?;
+            Instruction::decode_pair(opcode, next)
+        } else {
+            Instruction::decode(opcode)
+        };
+
+        match decoded {
+            Some(instruction) => {
+                // go to the next instruction
+                self.pc = self.pc.wrapping_add(instruction.size());
+                debug_assert_eq!(instruction.encode(), opcode);
+                debug!("{:03X}: {}", self.opcode_pc, instruction);
+                Ok(instruction)
             }
-            //DXYN Draw a sprite at position VX, VY with N bytes of sprite data
-            //starting at the address stored in I
-            //Set VF to 01 if any set pixels are changed to unset, and 00 otherwise
-            0xD000 => {
-                debug!("DRW V[{}], V[{}], nibble({})", x, y, n);
-                self.v[0xF] = 0;
-                for j in 0..n {
-                    let p = self.memory[(self.i + j as u16) as usize];
-                    for i in 0..8 {
-                        if (p & (128 >> i)) != 0 {
-                            let index = (u16::from(self.v[x as usize]) + i
-                                + (u16::from(self.v[y as usize]) + j) * 64)
-                                % 2048;
-                            if self.gfx[index as usize] == 1 {
-                                // bit flipped
-                                self.v[0xF] = 1;
-                            }
-                            self.gfx[index as usize] ^= 1;
-                            // self.draw_flag = true;
-                        }
-                    }
+            None => Err(CpuError::UnknownOpcode {
+                pc: self.opcode_pc,
+                opcode,
+            }),
+        }
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<StepOutcome, CpuError> {
+        use self::Instruction::*;
+
+        self.sound_flag = self.sound_timer > 0;
+
+        match instruction {
+            Sys { nnn } => self.op_0nnn(nnn)?,
+            Scd { n } => self.op_00cn(n),
+            Scu { n } => self.op_00dn(n),
+            Cls => self.op_00e0(),
+            Ret => self.op_00ee()?,
+            Scr => self.op_00fb(),
+            Scl => self.op_00fc(),
+            Exit => return Ok(self.op_00fd()),
+            Low => self.op_00fe(),
+            High => self.op_00ff(),
+            Jp { nnn } => self.op_1nnn(nnn),
+            Call { nnn } => self.op_2nnn(nnn)?,
+            SeByte { x, nn } => self.op_3xnn(x, nn),
+            SneByte { x, nn } => self.op_4xnn(x, nn),
+            SeReg { x, y } => self.op_5xy0(x, y),
+            SaveRange { x, y } => self.op_5xy2(x, y)?,
+            LoadRange { x, y } => self.op_5xy3(x, y)?,
+            LdByte { x, nn } => self.op_6xnn(x, nn),
+            AddByte { x, nn } => self.op_7xnn(x, nn),
+            LdReg { x, y } => self.op_8xy0(x, y),
+            Or { x, y } => self.op_8xy1(x, y),
+            And { x, y } => self.op_8xy2(x, y),
+            Xor { x, y } => self.op_8xy3(x, y),
+            AddReg { x, y } => self.op_8xy4(x, y),
+            Sub { x, y } => self.op_8xy5(x, y),
+            Shr { x, y } => self.op_8xy6(x, y),
+            Subn { x, y } => self.op_8xy7(x, y),
+            Shl { x, y } => self.op_8xye(x, y),
+            SneReg { x, y } => self.op_9xy0(x, y),
+            LdI { nnn } => self.op_annn(nnn),
+            JpV0 { nnn } => self.op_bnnn(nnn),
+            Rnd { x, nn } => self.op_cxnn(x, nn),
+            Drw { x, y, n } => {
+                self.op_dxyn(x, y, n)?;
+                if self.quirks.display_wait {
+                    self.vblank_wait = true;
                 }
-                self.draw_flag = true;
             }
-            0xE000 => match opcode & 0x00FF {
-                //EX9E Skip the following instruction if the key corresponding to
-                //the hex value currently stored in register VX is pressed
-                0x9E => {
-                    debug!("SKP V[{}]", x);
-                    if self.keyboard[self.v[x as usize] as usize] != 0 {
-                        self.pc += 2;
-                    }
-                }
-                //EXA1 Skip the following instruction if the key corresponding
-                //to the hex value currently stored in register VX is not pressed
-                0xA1 => {
-                    debug!("SKNP V[{}]", x);
-                    if self.keyboard[self.v[x as usize] as usize] == 0 {
-                        self.pc += 2;
-                    }
-                }
-                _ => panic!("unknown opcode {:X}", opcode),
-            },
-            0xF000 => match opcode & 0x00FF {
-                // FX07 Store the current value of the delay timer in register VX
-                0x07 => {
-                    debug!("LD V[{}], DT", x);
-                    self.v[x as usize] = self.delay_timer;
-                }
-                //FX0A Wait for a keypress and store the result in register VX
-                0x0A => {
-                    debug!("LD V[{}], DT", x);
-                    self.key_pressed = false;
-                    for i in 0..self.keyboard.len() {
-                        if self.keyboard[i] != 0 {
-                            self.v[x as usize] = i as u8;
-                            self.key_pressed = true;
-                            break;
-                        }
-                    }
-                    if !self.key_pressed {
-                        return;
-                    }
-                }
-                //FX15 Set the delay timer to the value of register VX
-                0x15 => {
-                    debug!("LD DT, V[{}]", x);
-                    self.delay_timer = self.v[x as usize];
-                }
-                //FX18 Set the sound timer to the value of register VX
-                0x18 => {
-                    debug!("LD ST, V[{}]", x);
-                    self.sound_timer = self.v[x as usize];
-                }
-                //FX1E Add the value stored in register VX to register I
-                0x1E => {
-                    debug!("ADD I, V[{}]", x);
-                    self.i += u16::from(self.v[x as usize]);
-                }
-                //FX29 Set I to the memory address of the sprite data corresponding to
-                //the hexadecimal digit stored in register VX
-                0x29 => {
-                    debug!("LD F, V[{}]", x);
-                    self.i = u16::from(self.v[x as usize]) * 0x5;
-                }
-                //FX33 	Store the binary-coded decimal equivalent of the value stored
-                //in register VX at addresses I, I+1, and I+2
-                0x33 => {
-                    debug!("LD B, V[{}]", x);
-                    self.memory[self.i as usize] = self.v[x as usize] / 100;
-                    self.memory[(self.i + 1) as usize] = (self.v[x as usize] / 10) % 10;
-                    self.memory[(self.i + 2) as usize] = (self.v[x as usize] % 100) % 10;
-                }
-                //FX55 Store the values of registers V0 to VX inclusive in memory starting at address I
-                //I is set to I + X + 1 after operation
-                0x55 => {
-                    debug!("LD [I], V[{}]", x);
-                    for i in 0..x as usize + 1 {
-                        self.memory[self.i as usize + i] = self.v[i];
-                    }
-                    if !self.load_store_quirk {
-                        self.i = self.i + x + 1;
-                    }
-                }
-                //FX65 Fill registers V0 to VX inclusive with the values
-                //stored in memory starting at address I
-                //I is set to I + X + 1 after operation
-                0x65 => {
-                    debug!("LD V[{}], [I]", x);
-                    for i in 0..x as usize + 1 {
-                        self.v[i] = self.memory[self.i as usize + i];
-                    }
-                    if !self.load_store_quirk {
-                        self.i = self.i + x + 1;
-                    }
-                }
-                _ => panic!("unknown opcode {:X}", opcode),
-            },
-            _ => panic!("unknown opcode {:X}", opcode),
+            Skp { x } => self.op_ex9e(x)?,
+            Sknp { x } => self.op_exa1(x)?,
+            LdILong { nnnn } => self.op_f000(nnnn),
+            Plane { n } => self.op_fn01(n),
+            Audio => self.op_f002()?,
+            LdVxDt { x } => self.op_fx07(x),
+            LdVxK { x } => return Ok(self.op_fx0a(x)),
+            LdDtVx { x } => self.op_fx15(x),
+            LdStVx { x } => self.op_fx18(x),
+            AddIVx { x } => self.op_fx1e(x),
+            LdFVx { x } => self.op_fx29(x),
+            LdHfVx { x } => self.op_fx30(x),
+            LdBVx { x } => self.op_fx33(x)?,
+            Pitch { x } => self.op_fx3a(x),
+            LdIVx { x } => self.op_fx55(x)?,
+            LdVxI { x } => self.op_fx65(x)?,
+            LdRVx { x } => self.op_fx75(x),
+            LdVxR { x } => self.op_fx85(x),
+        }
+
+        Ok(StepOutcome::Executed)
+    }
+
+    fn read(&self, addr: usize) -> Result<u8, CpuError> {
+        self.check_range(addr, 1)?;
+        Ok(self.memory[addr])
+    }
+
+    fn read_word(&self, addr: usize) -> Result<u16, CpuError> {
+        Ok(u16::from(self.read(addr)?) << 8 | u16::from(self.read(addr + 1)?))
+    }
+
+    // Skip the following instruction, which is 4 bytes long if it's F000 NNNN
+    fn skip(&mut self) {
+        let next = self.pc as usize;
+        let long = self.memory.get(next) == Some(&0xF0) && self.memory.get(next + 1) == Some(&0x00);
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
+    // Make sure len bytes starting at addr are inside memory
+    fn check_range(&self, addr: usize, len: usize) -> Result<(), CpuError> {
+        if addr + len > self.memory.len() {
+            return Err(CpuError::MemoryOutOfBounds {
+                pc: self.opcode_pc,
+                addr: addr.max(self.memory.len()),
+            });
+        }
+        Ok(())
+    }
+
+    // State of the key whose number is stored in VX
+    fn key(&self, x: u8) -> Result<u8, CpuError> {
+        let key = self.v[x as usize];
+        match self.keyboard.get(key as usize) {
+            Some(state) => Ok(*state),
+            None => Err(CpuError::InvalidKey {
+                pc: self.opcode_pc,
+                key,
+            }),
         }
     }
 
@@ -274,169 +434,486 @@ impl Cpu {
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
         }
-    }
-
-    pub fn toogle_quirks(&mut self) {
-        self.load_store_quirk = !self.load_store_quirk;
-        self.shift_quirk = !self.shift_quirk;
+        self.vblank_wait = false;
     }
 
     // Jump to a machine code routine at nnn.
     // This instruction is only used on the old computers on which Chip-8 was originally implemented. It is ignored by modern interpreters.
-    fn op_0nnn(&mut self, nnn: u16) {
-        debug!("SYS addr({})", nnn);
-        unimplemented!();
+    fn op_0nnn(&mut self, nnn: u16) -> Result<(), CpuError> {
+        Err(CpuError::MachineCodeRoutine {
+            pc: self.opcode_pc,
+            addr: nnn,
+        })
     }
 
-    // Clear the display
+    // Clear the selected planes of the display
     fn op_00e0(&mut self) {
-        for i in 0..self.gfx.len() {
-            self.gfx[i] = 0;
+        for pixel in self.gfx.iter_mut() {
+            *pixel &= !self.plane;
         }
         self.draw_flag = true;
-        debug!("CLS");
+    }
+    // Scroll the display down N pixels
+    fn op_00cn(&mut self, n: u8) {
+        self.scroll(0, n as isize);
+    }
+    // Scroll the display up N pixels
+    fn op_00dn(&mut self, n: u8) {
+        self.scroll(0, -(n as isize));
     }
     // Return from a subroutine
-    fn op_00ee(&mut self) {
+    fn op_00ee(&mut self) -> Result<(), CpuError> {
+        if self.sp == 0 {
+            return Err(CpuError::StackUnderflow { pc: self.opcode_pc });
+        }
         self.sp -= 1; // go down the stack
         self.pc = self.stack[self.sp as usize]; // return from the routine
-        debug!("RET to addr({})", self.pc);
+        Ok(())
+    }
+    // Scroll the display right 4 pixels
+    fn op_00fb(&mut self) {
+        self.scroll(4, 0);
+    }
+    // Scroll the display left 4 pixels
+    fn op_00fc(&mut self) {
+        self.scroll(-4, 0);
+    }
+    // Exit the interpreter
+    fn op_00fd(&mut self) -> StepOutcome {
+        self.pc = self.opcode_pc;
+        StepOutcome::Exited
+    }
+    // Switch to the 64x32 low resolution mode and clear the display
+    fn op_00fe(&mut self) {
+        self.hires = false;
+        self.clear_all_planes();
+    }
+    // Switch to the 128x64 high resolution mode and clear the display
+    fn op_00ff(&mut self) {
+        self.hires = true;
+        self.clear_all_planes();
     }
     // Jump to address NNN
     fn op_1nnn(&mut self, nnn: u16) {
-        debug!("JP addr({})", nnn);
         self.pc = nnn;
     }
     // Execute subroutine starting at address NNN
-    fn op_2nnn(&mut self, nnn: u16) {
-        debug!("CALL addr{}", nnn);
+    fn op_2nnn(&mut self, nnn: u16) -> Result<(), CpuError> {
+        if self.sp as usize >= self.stack.len() {
+            return Err(CpuError::StackOverflow { pc: self.opcode_pc });
+        }
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
         self.pc = nnn;
+        Ok(())
     }
     // Skip the following instruction if the value of register VX equals NN
-    fn op_3xnn(&mut self, x: u16, nn: u16) {
-        debug!("SE V[{}] byte({})", x, nn);
-        if self.v[x as usize] == nn as u8 {
-            self.pc += 2;
+    fn op_3xnn(&mut self, x: u8, nn: u8) {
+        if self.v[x as usize] == nn {
+            self.skip();
         }
     }
     // Skip the following instruction if the value of register VX is not equal to NN
-    fn op_4xnn(&mut self, x: u16, nn: u16) {
-        debug!("SNE V[{}], byte({})", x, nn);
-        if self.v[x as usize] != nn as u8 {
-            self.pc += 2;
+    fn op_4xnn(&mut self, x: u8, nn: u8) {
+        if self.v[x as usize] != nn {
+            self.skip();
         }
     }
     // Skip the following instruction if the value of register VX is equal to the value of register VY
-    fn op_5xy0(&mut self, x: u16, y: u16) {
-        debug!("SE V[{}], V[{}]", x, y);
+    fn op_5xy0(&mut self, x: u8, y: u8) {
         if self.v[x as usize] == self.v[y as usize] {
-            self.pc += 2;
+            self.skip();
         }
     }
+    // Store registers VX to VY inclusive in memory starting at address I
+    // VX can be bigger than VY, the registers are stored in that order, I doesn't change
+    fn op_5xy2(&mut self, x: u8, y: u8) -> Result<(), CpuError> {
+        let count = register_count(x, y);
+        self.check_range(self.i as usize, count)?;
+        for offset in 0..count {
+            self.memory[self.i as usize + offset] = self.v[nth_register(x, y, offset)];
+        }
+        Ok(())
+    }
+    // Load registers VX to VY inclusive from memory starting at address I
+    fn op_5xy3(&mut self, x: u8, y: u8) -> Result<(), CpuError> {
+        let count = register_count(x, y);
+        self.check_range(self.i as usize, count)?;
+        for offset in 0..count {
+            self.v[nth_register(x, y, offset)] = self.memory[self.i as usize + offset];
+        }
+        Ok(())
+    }
     // Store number NN in register VX
-    fn op_6xnn(&mut self, x: u16, nn: u16) {
-        debug!("LD V[{}], byte({})", x, nn);
-        self.v[x as usize] = nn as u8;
+    fn op_6xnn(&mut self, x: u8, nn: u8) {
+        self.v[x as usize] = nn;
     }
     // Add the value NN to register VX
-    fn op_7xnn(&mut self, x: u16, nn: u16) {
-        debug!("ADD V[{}], byte({})", x, nn);
-        self.v[x as usize] = self.v[x as usize].wrapping_add(nn as u8);
+    fn op_7xnn(&mut self, x: u8, nn: u8) {
+        self.v[x as usize] = self.v[x as usize].wrapping_add(nn);
     }
 
     // MATH STUFF
     // Store the value of register VY in register VX
-    fn op_8xy0(&mut self, x: u16, y: u16) {
-        debug!("LD V[{}], V[{}]", x, y);
+    fn op_8xy0(&mut self, x: u8, y: u8) {
         self.v[x as usize] = self.v[y as usize];
     }
 
     // Set VX to VX OR VY
-    fn op_8xy1(&mut self, x: u16, y: u16) {
-        debug!("OR V[{}], V[{}]", x, y);
+    fn op_8xy1(&mut self, x: u8, y: u8) {
         self.v[x as usize] |= self.v[y as usize];
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
     }
 
     // Set VX to VX AND VY
-    fn op_8xy2(&mut self, x: u16, y: u16) {
-        debug!("AND V[{}], V[{}]", x, y);
+    fn op_8xy2(&mut self, x: u8, y: u8) {
         self.v[x as usize] &= self.v[y as usize];
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
     }
 
     // Set VX to VX XOR VY
-    fn op_8xy3(&mut self, x: u16, y: u16) {
-        debug!("XOR V[{}], V[{}]", x, y);
+    fn op_8xy3(&mut self, x: u8, y: u8) {
         self.v[x as usize] ^= self.v[y as usize];
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
     }
 
     // Add the value of register VY to register VX
     // Set VF to 01 if a carry occurs
     // Set VF to 00 if a carry does not occur
-    fn op_8xy4(&mut self, x: u16, y: u16) {
-        debug!("ADD V[{}], V[{}]", x, y);
-        self.v[0xF] = 0;
+    // VF is written after the result so it holds the flag even when it's VX
+    fn op_8xy4(&mut self, x: u8, y: u8) {
         let (sum, carry) = self.v[x as usize].overflowing_add(self.v[y as usize]);
-        if carry {
-            self.v[0xF] = 1
-        };
         self.v[x as usize] = sum;
+        self.v[0xF] = carry as u8;
     }
 
     // Subtract the value of register VY from register VX
     // Set VF to 00 if a borrow occurs
     // Set VF to 01 if a borrow does not occur
-    fn op_8xy5(&mut self, x: u16, y: u16) {
-        debug!("SUB V[{}], V[{}]", x, y);
-        self.v[0xF] = 1;
+    fn op_8xy5(&mut self, x: u8, y: u8) {
         let (sub, borrow) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
-        if borrow {
-            self.v[0xF] = 0
-        };
         self.v[x as usize] = sub;
+        self.v[0xF] = !borrow as u8;
     }
 
     // Store the value of register VY shifted right one bit in register VX
     // Set register VF to the least significant bit prior to the shift
-    fn op_8xy6(&mut self, x: u16, y: u16) {
-        debug!("SHR V[{}] {{, V[{}]}}", x, y);
-        if !self.shift_quirk {
-            self.v[0xF] = self.v[y as usize] & 0x1;
-            self.v[y as usize] >>= 1;
-            self.v[x as usize] = self.v[y as usize];
+    // With the shift quirk VX is shifted in place instead, VY is never touched
+    fn op_8xy6(&mut self, x: u8, y: u8) {
+        let value = if self.quirks.shift {
+            self.v[x as usize]
         } else {
-            self.v[0xF] = self.v[x as usize] & 0x1;
-            self.v[x as usize] >>= 1;
-        }
+            self.v[y as usize]
+        };
+        self.v[x as usize] = value >> 1;
+        self.v[0xF] = value & 0x1;
     }
 
     // Set register VX to the value of VY minus VX
     // Set VF to 00 if a borrow occurs
     // Set VF to 01 if a borrow does not occur
-    fn op_8xy7(&mut self, x: u16, y: u16) {
-        debug!("SUBN V[{}], V[{}]", x, y);
-        self.v[0xF] = 1;
+    fn op_8xy7(&mut self, x: u8, y: u8) {
         let (sub, borrow) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
-        if borrow {
-            self.v[0xF] = 0
-        };
         self.v[x as usize] = sub;
+        self.v[0xF] = !borrow as u8;
     }
 
     //8XYE Store the value of register VY shifted left one bit in register VX
     //Set register VF to the most significant bit prior to the shift
-    fn op_8xye(&mut self, x: u16, y: u16) {
-        debug!("SHL V[{}] {{, V[{}]}}", x, y);
-        if !self.shift_quirk {
-            if !self.shift_quirk {
-                self.v[0xF] = (self.v[y as usize] >> 7) & 0x1;
-                self.v[y as usize] <<= 1;
-                self.v[x as usize] = self.v[y as usize];
-            }
+    //With the shift quirk VX is shifted in place instead, VY is never touched
+    fn op_8xye(&mut self, x: u8, y: u8) {
+        let value = if self.quirks.shift {
+            self.v[x as usize]
         } else {
-            self.v[0xF] = (self.v[x as usize] >> 7) & 0x1;
-            self.v[x as usize] <<= 1;
+            self.v[y as usize]
+        };
+        self.v[x as usize] = value << 1;
+        self.v[0xF] = value >> 7;
+    }
+
+    //9XY0 Skip the following instruction if the value of register VX
+    //is not equal to the value of register VY
+    fn op_9xy0(&mut self, x: u8, y: u8) {
+        if self.v[x as usize] != self.v[y as usize] {
+            self.skip();
+        }
+    }
+
+    //ANNN Store memory address NNN in register I
+    fn op_annn(&mut self, nnn: u16) {
+        self.i = nnn;
+    }
+
+    //BNNN Jump to address NNN + V0
+    //With the jump quirk it's BXNN and jumps to XNN + VX
+    fn op_bnnn(&mut self, nnn: u16) {
+        let x = if self.quirks.jump { nnn >> 8 } else { 0x0 };
+        self.pc = nnn + u16::from(self.v[x as usize]);
+    }
+
+    //CXNN Set VX to a random number with a mask of NN
+    fn op_cxnn(&mut self, x: u8, nn: u8) {
+        self.v[x as usize] = self.random.next_u8() & nn;
+    }
+
+    //DXYN Draw a sprite at position VX, VY with N bytes of sprite data
+    //starting at the address stored in I
+    //DXY0 draws a 16x16 sprite made of 32 bytes, two per row
+    //With both XO-CHIP planes selected the sprite for the second one follows
+    //the sprite for the first
+    //Set VF to 01 if any set pixels are changed to unset, and 00 otherwise
+    fn op_dxyn(&mut self, x: u8, y: u8, n: u8) -> Result<(), CpuError> {
+        let (cols, rows) = if n == 0 { (16, 16) } else { (8, n as usize) };
+        let sprite_len = rows * cols / 8;
+        let planes = self.plane.count_ones() as usize;
+        self.check_range(self.i as usize, sprite_len * planes)?;
+        let x0 = self.v[x as usize] as usize % self.width();
+        let y0 = self.v[y as usize] as usize % self.height();
+        self.v[0xF] = 0;
+        let mut addr = self.i as usize;
+        for plane in [1, 2].iter() {
+            if self.plane & plane != 0 {
+                if self.draw_sprite(addr, cols, rows, (x0, y0), *plane) {
+                    self.v[0xF] = 1;
+                }
+                addr += sprite_len;
+            }
+        }
+        self.draw_flag = true;
+        Ok(())
+    }
+
+    // XOR a sprite into one plane of the display, returns true if a pixel got unset
+    //The pixels that go past the edges are either clipped or wrapped depending
+    //on the clip quirk
+    fn draw_sprite(
+        &mut self,
+        addr: usize,
+        cols: usize,
+        rows: usize,
+        (x0, y0): (usize, usize),
+        plane: u8,
+    ) -> bool {
+        let (width, height) = (self.width(), self.height());
+        let row_bytes = cols / 8;
+        let mut collision = false;
+        for j in 0..rows {
+            let row = addr + j * row_bytes;
+            let p = if row_bytes == 2 {
+                u16::from(self.memory[row]) << 8 | u16::from(self.memory[row + 1])
+            } else {
+                u16::from(self.memory[row]) << 8
+            };
+            let mut py = y0 + j;
+            if py >= height {
+                if self.quirks.clip {
+                    break;
+                }
+                py %= height;
+            }
+            for i in 0..cols {
+                let mut px = x0 + i;
+                if px >= width {
+                    if self.quirks.clip {
+                        break;
+                    }
+                    px %= width;
+                }
+                if (p & (0x8000 >> i)) != 0 {
+                    let index = px + py * width;
+                    if self.gfx[index] & plane != 0 {
+                        // bit flipped
+                        collision = true;
+                    }
+                    self.gfx[index] ^= plane;
+                }
+            }
+        }
+        collision
+    }
+
+    //EX9E Skip the following instruction if the key corresponding to
+    //the hex value currently stored in register VX is pressed
+    fn op_ex9e(&mut self, x: u8) -> Result<(), CpuError> {
+        if self.key(x)? != 0 {
+            self.skip();
+        }
+        Ok(())
+    }
+
+    //EXA1 Skip the following instruction if the key corresponding
+    //to the hex value currently stored in register VX is not pressed
+    fn op_exa1(&mut self, x: u8) -> Result<(), CpuError> {
+        if self.key(x)? == 0 {
+            self.skip();
+        }
+        Ok(())
+    }
+
+    //F000 NNNN Store the 16 bit address NNNN in register I
+    fn op_f000(&mut self, nnnn: u16) {
+        self.i = nnnn;
+    }
+
+    //FN01 Select the XO-CHIP bitplanes N used by DXYN, 00E0 and the scrolls
+    fn op_fn01(&mut self, n: u8) {
+        self.plane = n & 0x3;
+    }
+
+    //F002 Load the 16 byte audio pattern buffer from memory starting at address I
+    fn op_f002(&mut self) -> Result<(), CpuError> {
+        let (start, len) = (self.i as usize, self.pattern.len());
+        self.check_range(start, len)?;
+        self.pattern
+            .clone_from_slice(&self.memory[start..start + len]);
+        self.pattern_set = true;
+        Ok(())
+    }
+
+    // FX07 Store the current value of the delay timer in register VX
+    fn op_fx07(&mut self, x: u8) {
+        self.v[x as usize] = self.delay_timer;
+    }
+
+    //FX0A Wait for a keypress and store the result in register VX
+    fn op_fx0a(&mut self, x: u8) -> StepOutcome {
+        self.key_pressed = false;
+        for i in 0..self.keyboard.len() {
+            if self.keyboard[i] != 0 {
+                self.v[x as usize] = i as u8;
+                self.key_pressed = true;
+                break;
+            }
+        }
+        if !self.key_pressed {
+            // run this instruction again on the next tick
+            self.pc = self.opcode_pc;
+            return StepOutcome::WaitingForKey;
+        }
+        StepOutcome::Executed
+    }
+
+    //FX15 Set the delay timer to the value of register VX
+    fn op_fx15(&mut self, x: u8) {
+        self.delay_timer = self.v[x as usize];
+    }
+
+    //FX18 Set the sound timer to the value of register VX
+    fn op_fx18(&mut self, x: u8) {
+        self.sound_timer = self.v[x as usize];
+    }
+
+    //FX1E Add the value stored in register VX to register I
+    fn op_fx1e(&mut self, x: u8) {
+        self.i = self.i.wrapping_add(u16::from(self.v[x as usize]));
+    }
+
+    //FX29 Set I to the memory address of the sprite data corresponding to
+    //the hexadecimal digit stored in register VX
+    fn op_fx29(&mut self, x: u8) {
+        self.i = u16::from(self.v[x as usize] & 0xF) * 0x5;
+    }
+
+    //FX30 Set I to the memory address of the 8x10 sprite for the hexadecimal
+    //digit stored in register VX, SUPER-CHIP only had 0-9
+    fn op_fx30(&mut self, x: u8) {
+        self.i = BIG_FONT_ADDR + u16::from(self.v[x as usize] & 0xF) * 10;
+    }
+
+    //FX33 	Store the binary-coded decimal equivalent of the value stored
+    //in register VX at addresses I, I+1, and I+2
+    fn op_fx33(&mut self, x: u8) -> Result<(), CpuError> {
+        self.check_range(self.i as usize, 3)?;
+        self.memory[self.i as usize] = self.v[x as usize] / 100;
+        self.memory[(self.i + 1) as usize] = (self.v[x as usize] / 10) % 10;
+        self.memory[(self.i + 2) as usize] = (self.v[x as usize] % 100) % 10;
+        Ok(())
+    }
+
+    //FX3A Set the audio pattern pitch to the value of register VX
+    fn op_fx3a(&mut self, x: u8) {
+        self.pitch = self.v[x as usize];
+    }
+
+    //FX55 Store the values of registers V0 to VX inclusive in memory starting at address I
+    //I is set to I + X + 1 after operation, see LoadStore for the other ways
+    fn op_fx55(&mut self, x: u8) -> Result<(), CpuError> {
+        self.check_range(self.i as usize, x as usize + 1)?;
+        for i in 0..x as usize + 1 {
+            self.memory[self.i as usize + i] = self.v[i];
+        }
+        self.i = self.i.wrapping_add(self.quirks.load_store.advance(x));
+        Ok(())
+    }
+
+    //FX65 Fill registers V0 to VX inclusive with the values
+    //stored in memory starting at address I
+    //I is set to I + X + 1 after operation, see LoadStore for the other ways
+    fn op_fx65(&mut self, x: u8) -> Result<(), CpuError> {
+        self.check_range(self.i as usize, x as usize + 1)?;
+        for i in 0..x as usize + 1 {
+            self.v[i] = self.memory[self.i as usize + i];
+        }
+        self.i = self.i.wrapping_add(self.quirks.load_store.advance(x));
+        Ok(())
+    }
+
+    //FX75 Store registers V0 to VX inclusive in the RPL user flags
+    fn op_fx75(&mut self, x: u8) {
+        let n = x as usize + 1;
+        self.rpl[..n].clone_from_slice(&self.v[..n]);
+    }
+
+    //FX85 Fill registers V0 to VX inclusive from the RPL user flags
+    fn op_fx85(&mut self, x: u8) {
+        let n = x as usize + 1;
+        self.v[..n].clone_from_slice(&self.rpl[..n]);
+    }
+
+    // Move the selected planes dx pixels right and dy pixels down, what
+    // scrolls in from the edges is blank
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let old = self.gfx;
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x - dx, y - dy);
+                let src = if sx >= 0 && sx < width && sy >= 0 && sy < height {
+                    old[(sx + sy * width) as usize]
+                } else {
+                    0
+                };
+                let index = (x + y * width) as usize;
+                self.gfx[index] = (self.gfx[index] & !self.plane) | (src & self.plane);
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    fn clear_all_planes(&mut self) {
+        for pixel in self.gfx.iter_mut() {
+            *pixel = 0;
         }
+        self.draw_flag = true;
+    }
+}
+
+// Number of registers from VX to VY inclusive
+fn register_count(x: u8, y: u8) -> usize {
+    (x as isize - y as isize).unsigned_abs() + 1
+}
+
+// The nth register going from VX to VY, counting down if X > Y
+fn nth_register(x: u8, y: u8, n: usize) -> usize {
+    if x <= y {
+        x as usize + n
+    } else {
+        x as usize - n
     }
 }
@@ -0,0 +1,175 @@
+// Behaviour differences between the CHIP-8 interpreters out there
+// Every flag is false on the original COSMAC VIP interpreter unless noted
+
+// What FX55/FX65 do to I after storing or loading V0 to VX
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStore {
+    // I + X + 1, the VIP and XO-CHIP
+    Increment,
+    // I + X, CHIP-48 and SUPER-CHIP 1.0
+    IncrementX,
+    // I untouched, SUPER-CHIP 1.1
+    Unchanged,
+}
+
+impl LoadStore {
+    // Values accepted by LoadStore::parse
+    pub const NAMES: [(&'static str, LoadStore); 3] = [
+        ("increment", LoadStore::Increment),
+        ("increment_x", LoadStore::IncrementX),
+        ("unchanged", LoadStore::Unchanged),
+    ];
+
+    pub fn parse(name: &str) -> Option<LoadStore> {
+        LoadStore::NAMES
+            .iter()
+            .find(|&&(value, _)| value == name)
+            .map(|&(_, load_store)| load_store)
+    }
+
+    // How far I moves after FX55/FX65 with this X
+    pub fn advance(self, x: u8) -> u16 {
+        match self {
+            LoadStore::Increment => u16::from(x) + 1,
+            LoadStore::IncrementX => u16::from(x),
+            LoadStore::Unchanged => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VX in place and ignore VY
+    pub shift: bool,
+    // Where FX55/FX65 leave I
+    pub load_store: LoadStore,
+    // 8XY1/8XY2/8XY3 reset VF to 0 (true on the VIP)
+    pub vf_reset: bool,
+    // BNNN is read as BXNN and jumps to XNN + VX
+    pub jump: bool,
+    // DXYN clips sprites at the right and bottom edges instead of wrapping them
+    // around, the starting position wraps either way and only the pixels drawn
+    // can set VF (true on the VIP)
+    pub clip: bool,
+    // DXYN waits for the next timers tick before running anything else, the
+    // vblank on the VIP, so no more than 60 sprites a second get drawn (true on the VIP)
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift: false,
+        load_store: LoadStore::Increment,
+        vf_reset: true,
+        jump: false,
+        clip: true,
+        display_wait: true,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        shift: true,
+        load_store: LoadStore::IncrementX,
+        vf_reset: false,
+        jump: true,
+        clip: true,
+        display_wait: false,
+    };
+
+    // SUPER-CHIP 1.0 kept the CHIP-48 behaviour
+    pub const SCHIP_1_0: Quirks = Quirks::CHIP_48;
+
+    // SUPER-CHIP 1.1 stopped FX55/FX65 from moving I at all
+    pub const SCHIP_1_1: Quirks = Quirks {
+        load_store: LoadStore::Unchanged,
+        ..Quirks::CHIP_48
+    };
+
+    pub const XO_CHIP: Quirks = Quirks {
+        shift: false,
+        load_store: LoadStore::Increment,
+        vf_reset: false,
+        jump: false,
+        clip: false,
+        display_wait: false,
+    };
+
+    // Preset names accepted by Quirks::preset
+    pub const PRESETS: [(&'static str, Quirks); 6] = [
+        ("vip", Quirks::COSMAC_VIP),
+        ("chip48", Quirks::CHIP_48),
+        ("schip10", Quirks::SCHIP_1_0),
+        ("schip11", Quirks::SCHIP_1_1),
+        ("schip", Quirks::SCHIP_1_1),
+        ("xochip", Quirks::XO_CHIP),
+    ];
+
+    // Names accepted by Quirks::set
+    pub const NAMES: [&'static str; 6] = [
+        "shift",
+        "load_store",
+        "vf_reset",
+        "jump",
+        "clip",
+        "display_wait",
+    ];
+
+    pub fn preset(name: &str) -> Option<Quirks> {
+        Quirks::PRESETS
+            .iter()
+            .find(|&&(preset, _)| preset == name)
+            .map(|&(_, quirks)| quirks)
+    }
+
+    // A preset followed by quirks to turn on or off, e.g. "vip,+shift,-display_wait",
+    // load_store also takes a value, e.g. "vip,load_store=increment_x"
+    // On error returns the preset or quirk name that wasn't recognized
+    pub fn parse(spec: &str) -> Result<Quirks, &str> {
+        let mut parts = spec.split(',');
+        let preset = parts.next().unwrap_or("");
+        let mut quirks = Quirks::preset(preset).ok_or(preset)?;
+        for part in parts {
+            if let Some(value) = part.strip_prefix("load_store=") {
+                quirks.load_store = LoadStore::parse(value).ok_or(part)?;
+                continue;
+            }
+            let (name, enabled) = if let Some(name) = part.strip_prefix('-') {
+                (name, false)
+            } else {
+                (part.trim_start_matches('+'), true)
+            };
+            if !quirks.set(name, enabled) {
+                return Err(name);
+            }
+        }
+        Ok(quirks)
+    }
+
+    // Turn a single quirk on or off by name, returns false if there's no such quirk
+    // load_store on is LoadStore::Unchanged and off is LoadStore::Increment
+    pub fn set(&mut self, name: &str, enabled: bool) -> bool {
+        let quirk = match name {
+            "shift" => &mut self.shift,
+            "load_store" => {
+                self.load_store = if enabled {
+                    LoadStore::Unchanged
+                } else {
+                    LoadStore::Increment
+                };
+                return true;
+            }
+            "vf_reset" => &mut self.vf_reset,
+            "jump" => &mut self.jump,
+            "clip" => &mut self.clip,
+            "display_wait" => &mut self.display_wait,
+            _ => return false,
+        };
+        *quirk = enabled;
+        true
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::COSMAC_VIP
+    }
+}
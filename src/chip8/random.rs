@@ -0,0 +1,66 @@
+// Where CXNN gets its random bytes from
+// Any FnMut() -> u8 works too, e.g. a closure reading a hardware RNG
+pub trait Random {
+    fn next_u8(&mut self) -> u8;
+
+    // Everything needed to resume the sequence later, for save states
+    // Sources that can't be captured return None
+    fn state(&self) -> Option<u64> {
+        None
+    }
+
+    // Resume from a value returned by state()
+    fn restore(&mut self, _state: u64) {}
+}
+
+impl<F: FnMut() -> u8> Random for F {
+    fn next_u8(&mut self) -> u8 {
+        self()
+    }
+}
+
+// xorshift64* generator, the same seed always gives the same bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    // Used by chip8::new and in place of 0, which would get xorshift stuck
+    pub const DEFAULT_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+    pub fn new(seed: u64) -> XorShift {
+        XorShift {
+            state: if seed == 0 {
+                XorShift::DEFAULT_SEED
+            } else {
+                seed
+            },
+        }
+    }
+}
+
+impl Default for XorShift {
+    fn default() -> XorShift {
+        XorShift::new(XorShift::DEFAULT_SEED)
+    }
+}
+
+impl Random for XorShift {
+    fn next_u8(&mut self) -> u8 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn state(&self) -> Option<u64> {
+        Some(self.state)
+    }
+
+    fn restore(&mut self, state: u64) {
+        *self = XorShift::new(state);
+    }
+}
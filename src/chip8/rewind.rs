@@ -0,0 +1,275 @@
+// Rewind history: one save state per frame kept in a ring buffer
+//
+// Only the newest state is stored whole, every older frame is the XOR of two
+// consecutive states with the runs of zeros squeezed out. Between two frames
+// almost nothing but a few registers and some gfx bytes change, so a frame
+// costs tens of bytes instead of the full 72 KiB state.
+
+use std::collections::VecDeque;
+
+use super::{Cpu, Random, StateError};
+
+pub struct Rewind {
+    frames: VecDeque<Vec<u8>>, // deltas, the oldest frame at the front
+    capacity: usize,
+    current: Vec<u8>, // the newest state, empty until the first push
+}
+
+impl Rewind {
+    // Keep up to `frames` frames of history
+    pub fn new(frames: usize) -> Rewind {
+        Rewind {
+            frames: VecDeque::with_capacity(frames),
+            capacity: frames,
+            current: Vec::new(),
+        }
+    }
+
+    // History for `seconds` at 60 frames per second
+    pub fn with_seconds(seconds: u32) -> Rewind {
+        Rewind::new(seconds as usize * 60)
+    }
+
+    // Number of frames we can still go back
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.current.clear();
+    }
+
+    // Record the machine as it is now, call it once per frame
+    pub fn push<R: Random>(&mut self, cpu: &Cpu<R>) {
+        let state = cpu.save_state();
+        if self.current.is_empty() || self.capacity == 0 {
+            self.current = state;
+            return;
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(delta(&self.current, &state));
+        self.current = state;
+    }
+
+    // Put the machine back one frame, returns false when the history ran out
+    // and the oldest frame we have is loaded instead
+    // The cpu must be the one the frames were pushed from
+    pub fn step_back<R: Random>(&mut self, cpu: &mut Cpu<R>) -> Result<bool, StateError> {
+        if self.current.is_empty() {
+            return Ok(false);
+        }
+        let stepped = match self.frames.pop_back() {
+            Some(delta) => {
+                apply(&mut self.current, &delta);
+                true
+            }
+            None => false,
+        };
+        cpu.load_state(&self.current)?;
+        Ok(stepped)
+    }
+}
+
+// Delta encoding: pairs of (zero bytes to skip, literal bytes) as LEB128
+// numbers, every literal count followed by that many XORed bytes
+fn delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < new.len() {
+        let start = pos;
+        while pos < new.len() && old[pos] == new[pos] {
+            pos += 1;
+        }
+        let skip = pos - start;
+        let start = pos;
+        while pos < new.len() && old[pos] != new[pos] {
+            pos += 1;
+        }
+        write_number(&mut out, skip);
+        write_number(&mut out, pos - start);
+        out.extend(
+            old[start..pos]
+                .iter()
+                .zip(&new[start..pos])
+                .map(|(a, b)| a ^ b),
+        );
+    }
+    out
+}
+
+fn apply(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut cursor = 0;
+    while cursor < delta.len() {
+        pos += read_number(delta, &mut cursor);
+        let len = read_number(delta, &mut cursor);
+        for (byte, x) in state[pos..pos + len]
+            .iter_mut()
+            .zip(&delta[cursor..cursor + len])
+        {
+            *byte ^= x;
+        }
+        pos += len;
+        cursor += len;
+    }
+}
+
+fn write_number(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_number(buf: &[u8], cursor: &mut usize) -> usize {
+    let mut n = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf[*cursor];
+        *cursor += 1;
+        n |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return n;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{new, Cpu, Quirks, STATE_SIZE};
+    use super::*;
+
+    // Counts in V0 and draws a digit that moves every frame
+    fn machine() -> Cpu {
+        let mut cpu = new(Quirks::CHIP_48);
+        cpu.load_rom(&[0x70, 0x01, 0xD0, 0x05, 0x12, 0x00]).unwrap();
+        cpu
+    }
+
+    fn frame(cpu: &mut Cpu) {
+        for _ in 0..3 {
+            cpu.cpu_tick().unwrap();
+        }
+        cpu.timers_tick();
+    }
+
+    // Runs `frames` frames pushing each one, returns the state after each
+    fn record(cpu: &mut Cpu, rewind: &mut Rewind, frames: usize) -> Vec<Vec<u8>> {
+        (0..frames)
+            .map(|_| {
+                frame(cpu);
+                rewind.push(cpu);
+                cpu.save_state()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut cpu = machine();
+        let mut rewind = Rewind::new(100);
+        let states = record(&mut cpu, &mut rewind, 10);
+        assert_eq!(rewind.len(), 9);
+        for n in (0..9).rev() {
+            assert_eq!(rewind.step_back(&mut cpu), Ok(true));
+            assert_eq!(cpu.save_state(), states[n]);
+        }
+        // out of history the oldest frame stays loaded
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.step_back(&mut cpu), Ok(false));
+        assert_eq!(cpu.save_state(), states[0]);
+    }
+
+    #[test]
+    fn drops_the_oldest_frames() {
+        let mut cpu = machine();
+        let mut rewind = Rewind::new(3);
+        let states = record(&mut cpu, &mut rewind, 6);
+        assert_eq!(rewind.len(), 3);
+        for n in (2..5).rev() {
+            assert_eq!(rewind.step_back(&mut cpu), Ok(true));
+            assert_eq!(cpu.save_state(), states[n]);
+        }
+        assert_eq!(rewind.step_back(&mut cpu), Ok(false));
+        assert_eq!(cpu.save_state(), states[2]);
+    }
+
+    #[test]
+    fn empty() {
+        let mut cpu = machine();
+        frame(&mut cpu);
+        let state = cpu.save_state();
+        let mut rewind = Rewind::new(10);
+        assert_eq!(rewind.step_back(&mut cpu), Ok(false));
+        assert_eq!(cpu.save_state(), state);
+
+        // a single frame has nothing before it
+        rewind.push(&cpu);
+        frame(&mut cpu);
+        assert_eq!(rewind.step_back(&mut cpu), Ok(false));
+        assert_eq!(cpu.save_state(), state);
+
+        rewind.clear();
+        frame(&mut cpu);
+        let state = cpu.save_state();
+        assert_eq!(rewind.step_back(&mut cpu), Ok(false));
+        assert_eq!(cpu.save_state(), state);
+    }
+
+    #[test]
+    fn identical_frames() {
+        let cpu = machine();
+        let state = cpu.save_state();
+        // all of it skipped and no literal bytes
+        let mut expected = Vec::new();
+        write_number(&mut expected, STATE_SIZE);
+        write_number(&mut expected, 0);
+        assert_eq!(delta(&state, &state), expected);
+
+        let mut rewind = Rewind::new(10);
+        for _ in 0..5 {
+            rewind.push(&cpu);
+        }
+        assert_eq!(rewind.len(), 4);
+        assert!(rewind.frames.iter().all(|frame| *frame == expected));
+        let mut back = machine();
+        back.delay_timer = 9;
+        assert_eq!(rewind.step_back(&mut back), Ok(true));
+        assert_eq!(back.save_state(), state);
+    }
+
+    #[test]
+    fn deltas() {
+        let old: Vec<u8> = (0..1000).map(|n| n as u8).collect();
+        let mut new = old.clone();
+        // changes at both ends and a literal run longer than a LEB128 byte
+        new[0] ^= 0xFF;
+        for byte in &mut new[300..500] {
+            *byte = !*byte;
+        }
+        new[999] = 0;
+        let delta = delta(&old, &new);
+        let mut state = old.clone();
+        apply(&mut state, &delta);
+        assert_eq!(state, new);
+        apply(&mut state, &delta);
+        assert_eq!(state, old);
+
+        for &n in &[0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, STATE_SIZE] {
+            let mut buf = Vec::new();
+            write_number(&mut buf, n);
+            let mut cursor = 0;
+            assert_eq!(read_number(&buf, &mut cursor), n);
+            assert_eq!(cursor, buf.len());
+        }
+    }
+}
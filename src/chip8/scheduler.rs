@@ -0,0 +1,187 @@
+// Keeps real time for a frontend: exactly 60 frames a second, however
+// unevenly the time comes in, and how many instructions to run in each
+//
+// The leftover time is kept in nanoseconds times 60, where a frame is exactly
+// a second, so nothing is lost to rounding and the timers don't drift. Speeds
+// in Hz that aren't a multiple of 60 carry the fraction of an instruction
+// over to the next frame the same way
+//
+// At Speed::CosmacVip a frame isn't a number of instructions but the machine
+// cycles a VIP had between two interrupts, see timing.rs
+use core::fmt;
+use core::time::Duration;
+
+use super::timing::{VIP_FRAME_CYCLES, VIP_INTERRUPT_CYCLES};
+
+const SECOND: u64 = 1_000_000_000;
+const FRAMES_PER_SECOND: u64 = 60;
+// Further behind than this (the process was stopped, the window was being
+// dragged) the lost frames are dropped instead of run all at once
+const MAX_FRAMES_BEHIND: u32 = 4;
+// What the speed hotkeys step through, in instructions per frame
+const SPEEDS: [u32; 15] = [1, 2, 3, 5, 7, 10, 15, 20, 30, 50, 100, 200, 500, 1000, 2000];
+// Machine cycles the interpreter gets every frame on a VIP
+const VIP_BUDGET: u64 = (VIP_FRAME_CYCLES - VIP_INTERRUPT_CYCLES) as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    // Instructions run at the start of every frame
+    PerFrame(u32),
+    // Instructions per second, spread over the frames
+    Hz(u32),
+    // As many instructions as a COSMAC VIP runs in a frame, which depends on
+    // what they are
+    CosmacVip,
+}
+
+impl Speed {
+    // "15" for 15 instructions per frame, "700hz" for 700 a second or "vip"
+    pub fn parse(spec: &str) -> Option<Speed> {
+        let spec = spec.trim();
+        if spec.eq_ignore_ascii_case("vip") {
+            return Some(Speed::CosmacVip);
+        }
+        let split = spec.len().saturating_sub(2);
+        let speed = match spec.get(split..) {
+            Some(suffix) if suffix.eq_ignore_ascii_case("hz") => {
+                Speed::Hz(spec[..split].trim().parse().ok()?)
+            }
+            _ => Speed::PerFrame(spec.parse().ok()?),
+        };
+        if speed.hz() == 0 {
+            return None;
+        }
+        Some(speed)
+    }
+
+    pub fn hz(self) -> u32 {
+        match self {
+            Speed::PerFrame(n) => n.saturating_mul(FRAMES_PER_SECOND as u32),
+            Speed::Hz(hz) => hz,
+            // about what the VIP manages on a typical game, for the hotkeys
+            Speed::CosmacVip => 600,
+        }
+    }
+
+    // The next of the hotkey speeds up, or the same speed at the top
+    pub fn faster(self) -> Speed {
+        let hz = self.hz();
+        SPEEDS
+            .iter()
+            .map(|&n| Speed::PerFrame(n))
+            .find(|speed| speed.hz() > hz)
+            .unwrap_or(self)
+    }
+
+    // The next of the hotkey speeds down, or the same speed at the bottom
+    pub fn slower(self) -> Speed {
+        let hz = self.hz();
+        SPEEDS
+            .iter()
+            .rev()
+            .map(|&n| Speed::PerFrame(n))
+            .find(|speed| speed.hz() < hz)
+            .unwrap_or(self)
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Speed::PerFrame(1) => write!(f, "1 instruction per frame"),
+            Speed::PerFrame(n) => write!(f, "{} instructions per frame", n),
+            Speed::Hz(hz) => write!(f, "{} instructions per second", hz),
+            Speed::CosmacVip => write!(f, "COSMAC VIP speed"),
+        }
+    }
+}
+
+pub struct Scheduler {
+    pub speed: Speed,
+    // nanoseconds times 60 since the last frame, always less than a second
+    time: u64,
+    // instructions times 60 owed to the next frame at a Hz speed
+    carry: u64,
+    // instructions left in the frame
+    left: u32,
+    // Cpu::cycles() the frame ends at, while at CosmacVip speed
+    end: Option<u64>,
+}
+
+impl Scheduler {
+    pub fn new(speed: Speed) -> Scheduler {
+        Scheduler {
+            speed,
+            time: 0,
+            carry: 0,
+            left: 0,
+            end: None,
+        }
+    }
+
+    // Add the time since the last call, returns how many frames are due.
+    // Each one calls start_frame, runs instructions while more says so and
+    // ticks the timers once
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        // no overflow even if the caller was gone for years
+        let elapsed = elapsed.min(Duration::from_secs(1));
+        self.time += elapsed.as_nanos() as u64 * FRAMES_PER_SECOND;
+        let frames = self.time / SECOND;
+        self.time %= SECOND;
+        (frames as u32).min(MAX_FRAMES_BEHIND)
+    }
+
+    // How long until advance returns another frame, to sleep in between
+    pub fn until_next_frame(&self) -> Duration {
+        let left = SECOND - self.time;
+        Duration::from_nanos(left.div_ceil(FRAMES_PER_SECOND))
+    }
+
+    // The instructions to run in the frame about to start, at CosmacVip
+    // speed they're only known as they run and this is 0
+    pub fn instructions(&mut self) -> u32 {
+        match self.speed {
+            Speed::PerFrame(n) => n,
+            Speed::CosmacVip => 0,
+            Speed::Hz(hz) => {
+                self.carry += u64::from(hz);
+                let instructions = self.carry / FRAMES_PER_SECOND;
+                self.carry %= FRAMES_PER_SECOND;
+                instructions as u32
+            }
+        }
+    }
+
+    // cycles is Cpu::cycles() as the frame starts
+    pub fn start_frame(&mut self, cycles: u64) {
+        match self.speed {
+            Speed::CosmacVip => {
+                // The last instruction of a frame usually runs past its end,
+                // the next frame is that much shorter. A frame that stopped
+                // early (the VIP waiting for the interrupt) or a speed change
+                // starts afresh
+                let start = match self.end {
+                    Some(end) if cycles >= end && cycles - end < VIP_BUDGET => end,
+                    _ => cycles,
+                };
+                self.end = Some(start + VIP_BUDGET);
+            }
+            _ => {
+                self.left = self.instructions();
+                self.end = None;
+            }
+        }
+    }
+
+    // Whether to run another instruction in the frame, cycles is Cpu::cycles()
+    pub fn more(&mut self, cycles: u64) -> bool {
+        match self.speed {
+            Speed::CosmacVip => self.end.is_some_and(|end| cycles < end),
+            _ if self.left > 0 => {
+                self.left -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,360 @@
+// Save states: the whole machine in a flat little endian binary format
+//
+// magic "CH8S", format version (u16), hash of the loaded ROM (u64), then the
+// machine itself in the order of save_state_to below
+// A state can only be loaded into a Cpu running the same ROM
+
+use super::{Cpu, LoadStore, Quirks, Random, StateError};
+
+const MAGIC: &[u8; 4] = b"CH8S";
+const VERSION: u16 = 2;
+
+// Size in bytes of every save state
+pub const STATE_SIZE: usize = 4 + 2 + 8 // header
+    + 0x10000 + 16 + 2 + 2 + 1 + 16 * 2 // memory, v, i, pc, sp, stack
+    + 1 + 1 + 16 // timers, keyboard
+    + 128 * 64 + 1 + 16 + 1 + 16 + 1 + 1 // gfx, hires, rpl, plane, pattern, pattern_set, pitch
+    + 1 + 1 + 8 // quirks, vblank_wait, cycles
+    + 1 + 8; // random source
+
+// FNV-1a, good enough to tell ROMs apart
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf[self.pos..self.pos + bytes.len()].clone_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes(&[value]);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, out: &mut [u8]) {
+        out.clone_from_slice(&self.buf[self.pos..self.pos + out.len()]);
+        self.pos += out.len();
+    }
+
+    fn u8(&mut self) -> u8 {
+        let mut bytes = [0; 1];
+        self.bytes(&mut bytes);
+        bytes[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        let mut bytes = [0; 2];
+        self.bytes(&mut bytes);
+        u16::from_le_bytes(bytes)
+    }
+
+    fn u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+// load_store takes bit 1 for Unchanged and bit 6 for IncrementX, neither is
+// Increment
+fn quirk_bits(quirks: &Quirks) -> u8 {
+    [
+        quirks.shift,
+        quirks.load_store == LoadStore::Unchanged,
+        quirks.vf_reset,
+        quirks.jump,
+        quirks.clip,
+        quirks.display_wait,
+        quirks.load_store == LoadStore::IncrementX,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |bits, (n, &on)| bits | (on as u8) << n)
+}
+
+fn quirks_from_bits(bits: u8) -> Quirks {
+    let on = |n: u8| bits & (1 << n) != 0;
+    Quirks {
+        shift: on(0),
+        load_store: if on(6) {
+            LoadStore::IncrementX
+        } else if on(1) {
+            LoadStore::Unchanged
+        } else {
+            LoadStore::Increment
+        },
+        vf_reset: on(2),
+        jump: on(3),
+        clip: on(4),
+        display_wait: on(5),
+    }
+}
+
+impl<R: Random> Cpu<R> {
+    // Write a save state into buf, which doesn't need an allocator
+    pub fn save_state_to(&self, buf: &mut [u8; STATE_SIZE]) {
+        let mut w = Writer { buf, pos: 0 };
+        w.bytes(MAGIC);
+        w.u16(VERSION);
+        w.u64(self.rom_hash);
+
+        w.bytes(&self.memory);
+        w.bytes(&self.v);
+        w.u16(self.i);
+        w.u16(self.pc);
+        w.u8(self.sp);
+        for addr in self.stack.iter() {
+            w.u16(*addr);
+        }
+
+        w.u8(self.delay_timer);
+        w.u8(self.sound_timer);
+        w.bytes(&self.keyboard);
+
+        w.bytes(&self.gfx);
+        w.u8(self.hires as u8);
+        w.bytes(&self.rpl);
+        w.u8(self.plane);
+        w.bytes(&self.pattern);
+        w.u8(self.pattern_set as u8);
+        w.u8(self.pitch);
+
+        w.u8(quirk_bits(&self.quirks));
+        w.u8(self.vblank_wait as u8);
+        w.u64(self.cycles);
+
+        match self.random.state() {
+            Some(state) => {
+                w.u8(1);
+                w.u64(state);
+            }
+            None => {
+                w.u8(0);
+                w.u64(0);
+            }
+        }
+        debug_assert_eq!(w.pos, STATE_SIZE);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save_state(&self) -> Vec<u8> {
+        use std::convert::TryInto;
+
+        let mut buf = vec![0; STATE_SIZE];
+        self.save_state_to((&mut buf[..]).try_into().unwrap());
+        buf
+    }
+
+    // Restore a state made by save_state, the machine is left untouched if
+    // the state is invalid or was saved with another ROM
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        if state.len() < 6 || &state[..4] != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let mut r = Reader { buf: state, pos: 4 };
+        let version = r.u16();
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if state.len() != STATE_SIZE {
+            return Err(StateError::Corrupted);
+        }
+        if r.u64() != self.rom_hash {
+            return Err(StateError::WrongRom);
+        }
+        // sp is the only value that could make the interpreter index out of
+        // bounds, it comes right after memory, v, i and pc
+        let sp_offset = r.pos + 0x10000 + 16 + 2 + 2;
+        if state[sp_offset] as usize > self.stack.len() {
+            return Err(StateError::Corrupted);
+        }
+
+        r.bytes(&mut self.memory);
+        r.bytes(&mut self.v);
+        self.i = r.u16();
+        self.pc = r.u16();
+        self.opcode_pc = self.pc;
+        self.sp = r.u8();
+        for addr in self.stack.iter_mut() {
+            *addr = r.u16();
+        }
+
+        self.delay_timer = r.u8();
+        self.sound_timer = r.u8();
+        r.bytes(&mut self.keyboard);
+
+        r.bytes(&mut self.gfx);
+        self.hires = r.u8() != 0;
+        r.bytes(&mut self.rpl);
+        self.plane = r.u8() & 0x3;
+        r.bytes(&mut self.pattern);
+        self.pattern_set = r.u8() != 0;
+        self.pitch = r.u8();
+
+        self.quirks = quirks_from_bits(r.u8());
+        self.vblank_wait = r.u8() != 0;
+        self.cycles = r.u64();
+
+        let has_random = r.u8() != 0;
+        let random = r.u64();
+        if has_random {
+            self.random.restore(random);
+        }
+
+        self.draw_flag = true;
+        self.sound_flag = self.sound_timer > 0;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::super::{new, Cpu, Quirks, StepOutcome};
+    use super::*;
+
+    // CXNN into V0 then V1 forever
+    const ROM: [u8; 6] = [0xC0, 0xFF, 0xC1, 0xFF, 0x12, 0x00];
+
+    fn machine() -> Cpu {
+        let mut cpu = new(Quirks::CHIP_48);
+        cpu.load_rom(&ROM).unwrap();
+        cpu
+    }
+
+    fn randoms(cpu: &mut Cpu, count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|_| {
+                assert_eq!(cpu.cpu_tick(), Ok(StepOutcome::Executed));
+                cpu.v[0] ^ cpu.v[1]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut cpu = machine();
+        randoms(&mut cpu, 7);
+        cpu.v[5] = 0x55;
+        cpu.i = 0x345;
+        cpu.memory[0xFFF] = 0xAB;
+        cpu.gfx[100] = 3;
+        cpu.delay_timer = 20;
+        cpu.sound_timer = 10;
+        cpu.stack[0] = 0x208;
+        cpu.sp = 1;
+        cpu.quirks = Quirks::XO_CHIP;
+        let state = cpu.save_state();
+        let (pc, cycles) = (cpu.pc, cpu.cycles());
+        assert!(cycles > 0);
+        // what comes next, which also moves the random source on
+        let next = randoms(&mut cpu, 20);
+
+        cpu.v = [0xEE; 16];
+        cpu.i = 0;
+        cpu.memory[0xFFF] = 0;
+        cpu.gfx = [0; 128 * 64];
+        cpu.delay_timer = 0;
+        cpu.sound_timer = 0;
+        cpu.sp = 0;
+        cpu.quirks = Quirks::CHIP_48;
+        cpu.load_state(&state).unwrap();
+
+        assert_eq!(cpu.save_state(), state);
+        assert_eq!(cpu.v[5], 0x55);
+        assert_eq!((cpu.i, cpu.pc), (0x345, pc));
+        // COSMAC VIP timing goes on from the same cycle count
+        assert_eq!(cpu.cycles(), cycles);
+        assert_eq!(cpu.memory[0xFFF], 0xAB);
+        assert_eq!(cpu.gfx[100], 3);
+        assert_eq!((cpu.delay_timer, cpu.sound_timer), (20, 10));
+        assert_eq!(cpu.stack(), [0x208]);
+        assert_eq!(cpu.quirks, Quirks::XO_CHIP);
+        // the random source goes on where it was
+        assert_eq!(randoms(&mut cpu, 20), next);
+    }
+
+    #[test]
+    fn quirks() {
+        for &(name, quirks) in Quirks::PRESETS.iter() {
+            let mut cpu = machine();
+            cpu.quirks = quirks;
+            let state = cpu.save_state();
+            cpu.quirks = Quirks::default();
+            cpu.load_state(&state).unwrap();
+            assert_eq!(cpu.quirks, quirks, "{}", name);
+        }
+    }
+
+    // load_state fails with error and leaves the machine as it was
+    fn rejects(state: &[u8], error: StateError) {
+        let mut cpu = machine();
+        let before = cpu.save_state();
+        assert_eq!(cpu.load_state(state), Err(error));
+        assert_eq!(cpu.save_state(), before);
+    }
+
+    #[test]
+    fn errors() {
+        let mut cpu = machine();
+        randoms(&mut cpu, 5);
+        let state = cpu.save_state();
+
+        rejects(b"", StateError::BadMagic);
+        rejects(b"CH8S", StateError::BadMagic);
+        rejects(&[0; STATE_SIZE], StateError::BadMagic);
+
+        let mut future = state.clone();
+        future[4..6].copy_from_slice(&3u16.to_le_bytes());
+        rejects(&future, StateError::UnsupportedVersion(3));
+        // version 1 states had no cycle count
+        let mut old = state.clone();
+        old[4..6].copy_from_slice(&1u16.to_le_bytes());
+        rejects(&old, StateError::UnsupportedVersion(1));
+
+        rejects(&state[..6], StateError::Corrupted);
+        rejects(&state[..STATE_SIZE - 1], StateError::Corrupted);
+        let mut longer = state.clone();
+        longer.push(0);
+        rejects(&longer, StateError::Corrupted);
+
+        let mut other = new(Quirks::CHIP_48);
+        other.load_rom(&[0x12, 0x00]).unwrap();
+        assert_eq!(other.load_state(&state), Err(StateError::WrongRom));
+
+        // a stack pointer past the stack would panic on the next RET
+        let sp = 4 + 2 + 8 + 0x10000 + 16 + 2 + 2;
+        assert_eq!(state[sp], 0);
+        for &bad in &[17, 0xFF] {
+            let mut corrupted = state.clone();
+            corrupted[sp] = bad;
+            rejects(&corrupted, StateError::Corrupted);
+        }
+        let mut full = state;
+        full[sp] = 16;
+        cpu.load_state(&full).unwrap();
+        assert_eq!(cpu.stack().len(), 16);
+    }
+}
@@ -0,0 +1,1765 @@
+// Every instruction run once from a known state, under every combination of
+// quirks that shouldn't change its result
+//
+// A case lists the machine before the instruction and only what the
+// instruction is expected to change. The expected machine is built from both
+// and compared field by field with the one that ran the instruction, so side
+// effects nobody asked for (a clobbered VY, a stray VF) fail too
+
+use super::{with_random, Cpu, CpuError, LoadStore, Quirks, RomError, StepOutcome, MAX_ROM_SIZE};
+
+// What CXNN gets from the random source
+fn random() -> u8 {
+    0xA5
+}
+
+type TestCpu = Cpu<fn() -> u8>;
+
+#[derive(Clone, Copy)]
+struct State {
+    v: &'static [(usize, u8)],
+    i: Option<u16>,
+    pc: Option<u16>,
+    // return addresses, the length is the stack pointer
+    stack: Option<&'static [u16]>,
+    memory: &'static [(usize, &'static [u8])],
+    delay_timer: Option<u8>,
+    sound_timer: Option<u8>,
+    keys: &'static [u8],
+    // (x, y, row) with '.' for blank pixels, '#' for plane 1, '+' for plane 2
+    // and '*' for both, replaces the whole screen
+    screen: Option<&'static [(usize, usize, &'static str)]>,
+    hires: Option<bool>,
+    plane: Option<u8>,
+    rpl: &'static [(usize, u8)],
+    pattern: Option<[u8; 16]>,
+    pitch: Option<u8>,
+    vblank_wait: Option<bool>,
+}
+
+const SAME: State = State {
+    v: &[],
+    i: None,
+    pc: None,
+    stack: None,
+    memory: &[],
+    delay_timer: None,
+    sound_timer: None,
+    keys: &[],
+    screen: None,
+    hires: None,
+    plane: None,
+    rpl: &[],
+    pattern: None,
+    pitch: None,
+    vblank_wait: None,
+};
+
+struct Case {
+    name: &'static str,
+    // placed at pc, more than one word for F000 NNNN or what a skip skips
+    code: &'static [u16],
+    // quirks the case depends on, it runs with every value of the others
+    quirks: &'static [(&'static str, bool)],
+    // the same for load_store, which isn't on or off
+    load_store: Option<LoadStore>,
+    before: State,
+    after: State,
+    outcome: Result<StepOutcome, CpuError>,
+}
+
+const CASE: Case = Case {
+    name: "",
+    code: &[],
+    quirks: &[],
+    load_store: None,
+    before: SAME,
+    after: SAME,
+    outcome: Ok(StepOutcome::Executed),
+};
+
+fn apply(cpu: &mut TestCpu, state: &State) {
+    for &(x, value) in state.v {
+        cpu.v[x] = value;
+    }
+    if let Some(i) = state.i {
+        cpu.i = i;
+    }
+    if let Some(pc) = state.pc {
+        cpu.pc = pc;
+    }
+    if let Some(stack) = state.stack {
+        cpu.stack[..stack.len()].copy_from_slice(stack);
+        cpu.sp = stack.len() as u8;
+    }
+    for &(addr, bytes) in state.memory {
+        cpu.memory[addr..addr + bytes.len()].copy_from_slice(bytes);
+    }
+    if let Some(delay_timer) = state.delay_timer {
+        cpu.delay_timer = delay_timer;
+    }
+    if let Some(sound_timer) = state.sound_timer {
+        cpu.sound_timer = sound_timer;
+    }
+    for &key in state.keys {
+        cpu.key_down(key);
+    }
+    if let Some(hires) = state.hires {
+        cpu.hires = hires;
+    }
+    if let Some(screen) = state.screen {
+        cpu.gfx = [0; 128 * 64];
+        for &(x, y, row) in screen {
+            for (n, pixel) in row.chars().enumerate() {
+                let index = x + n + y * cpu.width();
+                cpu.gfx[index] = match pixel {
+                    '.' => 0,
+                    '#' => 1,
+                    '+' => 2,
+                    '*' => 3,
+                    _ => panic!("bad pixel {:?}", pixel),
+                };
+            }
+        }
+    }
+    if let Some(plane) = state.plane {
+        cpu.plane = plane;
+    }
+    for &(n, value) in state.rpl {
+        cpu.rpl[n] = value;
+    }
+    if let Some(pattern) = state.pattern {
+        cpu.pattern = pattern;
+        cpu.pattern_set = true;
+    }
+    if let Some(pitch) = state.pitch {
+        cpu.pitch = pitch;
+    }
+    if let Some(vblank_wait) = state.vblank_wait {
+        cpu.vblank_wait = vblank_wait;
+    }
+}
+
+// The machine before running the case
+fn machine(case: &Case, quirks: Quirks) -> TestCpu {
+    let mut cpu = with_random(quirks, random as fn() -> u8);
+    apply(&mut cpu, &case.before);
+    let pc = cpu.pc as usize;
+    for (n, word) in case.code.iter().enumerate() {
+        cpu.memory[pc + n * 2..pc + n * 2 + 2].copy_from_slice(&word.to_be_bytes());
+    }
+    cpu
+}
+
+// Every combination of quirks that agrees with the ones the case asks for
+fn combinations(case: &Case) -> impl Iterator<Item = Quirks> + '_ {
+    let none = Quirks {
+        shift: false,
+        load_store: LoadStore::Increment,
+        vf_reset: false,
+        jump: false,
+        clip: false,
+        display_wait: false,
+    };
+    let names = || Quirks::NAMES.iter().filter(|&&name| name != "load_store");
+    LoadStore::NAMES
+        .iter()
+        .flat_map(move |&(_, load_store)| {
+            (0..1 << names().count()).map(move |bits: u32| {
+                let mut quirks = Quirks { load_store, ..none };
+                for (n, name) in names().enumerate() {
+                    quirks.set(name, bits & (1 << n) != 0);
+                }
+                quirks
+            })
+        })
+        .filter(move |quirks| {
+            case.load_store
+                .is_none_or(|wanted| wanted == quirks.load_store)
+                && case.quirks.iter().all(|&(name, enabled)| {
+                    let mut wanted = *quirks;
+                    assert!(wanted.set(name, enabled), "unknown quirk {}", name);
+                    wanted == *quirks
+                })
+        })
+}
+
+macro_rules! check {
+    ($case:expr, $quirks:expr, $actual:expr, $expected:expr, $field:ident) => {
+        assert_eq!(
+            $actual.$field,
+            $expected.$field,
+            "{} with {:?}: {}",
+            $case.name,
+            $quirks,
+            stringify!($field)
+        );
+    };
+}
+
+fn run(cases: &[Case]) {
+    for case in cases {
+        for quirks in combinations(case) {
+            let mut actual = machine(case, quirks);
+            let outcome = actual.cpu_tick();
+
+            let mut expected = machine(case, quirks);
+            if case.outcome == Ok(StepOutcome::Executed) {
+                let size = if case.code[0] == 0xF000 { 4 } else { 2 };
+                expected.pc += size;
+            }
+            apply(&mut expected, &case.after);
+
+            assert_eq!(
+                outcome, case.outcome,
+                "{} with {:?}: outcome",
+                case.name, quirks
+            );
+            check!(case, quirks, actual, expected, v);
+            check!(case, quirks, actual, expected, i);
+            check!(case, quirks, actual, expected, pc);
+            check!(case, quirks, actual, expected, sp);
+            check!(case, quirks, actual, expected, stack);
+            check!(case, quirks, actual, expected, delay_timer);
+            check!(case, quirks, actual, expected, sound_timer);
+            check!(case, quirks, actual, expected, keyboard);
+            check!(case, quirks, actual, expected, hires);
+            check!(case, quirks, actual, expected, plane);
+            check!(case, quirks, actual, expected, rpl);
+            check!(case, quirks, actual, expected, pattern);
+            check!(case, quirks, actual, expected, pattern_set);
+            check!(case, quirks, actual, expected, pitch);
+            check!(case, quirks, actual, expected, vblank_wait);
+            if actual.memory[..] != expected.memory[..] {
+                let addr = (0..)
+                    .find(|&a| actual.memory[a] != expected.memory[a])
+                    .unwrap();
+                panic!(
+                    "{} with {:?}: memory at {:04X} is {:02X}, expected {:02X}",
+                    case.name, quirks, addr, actual.memory[addr], expected.memory[addr]
+                );
+            }
+            if actual.gfx[..] != expected.gfx[..] {
+                let index = (0..).find(|&n| actual.gfx[n] != expected.gfx[n]).unwrap();
+                panic!(
+                    "{} with {:?}: pixel ({}, {}) is {}, expected {}",
+                    case.name,
+                    quirks,
+                    index % actual.width(),
+                    index / actual.width(),
+                    actual.gfx[index],
+                    expected.gfx[index]
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn flow() {
+    run(&[
+        Case {
+            name: "0NNN is refused",
+            code: &[0x0123],
+            outcome: Err(CpuError::MachineCodeRoutine {
+                pc: 0x200,
+                addr: 0x123,
+            }),
+            ..CASE
+        },
+        Case {
+            name: "00EE returns",
+            code: &[0x00EE],
+            before: State {
+                pc: Some(0x400),
+                stack: Some(&[0x202]),
+                ..SAME
+            },
+            after: State {
+                pc: Some(0x202),
+                stack: Some(&[]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "00EE with an empty stack",
+            code: &[0x00EE],
+            outcome: Err(CpuError::StackUnderflow { pc: 0x200 }),
+            ..CASE
+        },
+        Case {
+            name: "1NNN jumps",
+            code: &[0x1345],
+            after: State {
+                pc: Some(0x345),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "2NNN calls",
+            code: &[0x2345],
+            after: State {
+                pc: Some(0x345),
+                stack: Some(&[0x202]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "2NNN with a full stack",
+            code: &[0x2345],
+            before: State {
+                stack: Some(&[0x202; 16]),
+                ..SAME
+            },
+            outcome: Err(CpuError::StackOverflow { pc: 0x200 }),
+            ..CASE
+        },
+        Case {
+            name: "3XNN skips when equal",
+            code: &[0x3342],
+            before: State {
+                v: &[(0x3, 0x42)],
+                ..SAME
+            },
+            after: State {
+                pc: Some(0x204),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "3XNN doesn't skip when different",
+            code: &[0x3341],
+            before: State {
+                v: &[(0x3, 0x42)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "3XNN skips all of F000 NNNN",
+            code: &[0x3342, 0xF000, 0x1234],
+            before: State {
+                v: &[(0x3, 0x42)],
+                ..SAME
+            },
+            after: State {
+                pc: Some(0x206),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "4XNN skips when different",
+            code: &[0x4341],
+            before: State {
+                v: &[(0x3, 0x42)],
+                ..SAME
+            },
+            after: State {
+                pc: Some(0x204),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "4XNN doesn't skip when equal",
+            code: &[0x4342],
+            before: State {
+                v: &[(0x3, 0x42)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "5XY0 skips when equal",
+            code: &[0x5340],
+            before: State {
+                v: &[(0x3, 0x42), (0x4, 0x42)],
+                ..SAME
+            },
+            after: State {
+                pc: Some(0x204),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "5XY0 doesn't skip when different",
+            code: &[0x5340],
+            before: State {
+                v: &[(0x3, 0x42), (0x4, 0x43)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "9XY0 skips when different",
+            code: &[0x9340],
+            before: State {
+                v: &[(0x3, 0x42), (0x4, 0x43)],
+                ..SAME
+            },
+            after: State {
+                pc: Some(0x204),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "9XY0 doesn't skip when equal",
+            code: &[0x9340],
+            before: State {
+                v: &[(0x3, 0x42), (0x4, 0x42)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "BNNN jumps to NNN + V0",
+            code: &[0xB234],
+            quirks: &[("jump", false)],
+            before: State {
+                v: &[(0x0, 0x10), (0x2, 0x20)],
+                ..SAME
+            },
+            after: State {
+                pc: Some(0x244),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "BXNN jumps to XNN + VX",
+            code: &[0xB234],
+            quirks: &[("jump", true)],
+            before: State {
+                v: &[(0x0, 0x10), (0x2, 0x20)],
+                ..SAME
+            },
+            after: State {
+                pc: Some(0x254),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "unknown opcodes are refused",
+            code: &[0x5121],
+            outcome: Err(CpuError::UnknownOpcode {
+                pc: 0x200,
+                opcode: 0x5121,
+            }),
+            ..CASE
+        },
+    ]);
+}
+
+#[test]
+fn registers() {
+    run(&[
+        Case {
+            name: "6XNN loads",
+            code: &[0x6A12],
+            after: State {
+                v: &[(0xA, 0x12)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "7XNN wraps without touching VF",
+            code: &[0x7A02],
+            before: State {
+                v: &[(0xA, 0xFF), (0xF, 0x55)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0xA, 0x01)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY0 copies",
+            code: &[0x8120],
+            before: State {
+                v: &[(0x1, 0x11), (0x2, 0x22)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x22)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY1 resets VF",
+            code: &[0x8121],
+            quirks: &[("vf_reset", true)],
+            before: State {
+                v: &[(0x1, 0x0F), (0x2, 0xF0), (0xF, 0x55)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0xFF), (0xF, 0x00)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY1 leaves VF",
+            code: &[0x8121],
+            quirks: &[("vf_reset", false)],
+            before: State {
+                v: &[(0x1, 0x0F), (0x2, 0xF0), (0xF, 0x55)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0xFF)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY2 resets VF",
+            code: &[0x8122],
+            quirks: &[("vf_reset", true)],
+            before: State {
+                v: &[(0x1, 0x3C), (0x2, 0x0F), (0xF, 0x55)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x0C), (0xF, 0x00)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY2 leaves VF",
+            code: &[0x8122],
+            quirks: &[("vf_reset", false)],
+            before: State {
+                v: &[(0x1, 0x3C), (0x2, 0x0F), (0xF, 0x55)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x0C)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY3 resets VF",
+            code: &[0x8123],
+            quirks: &[("vf_reset", true)],
+            before: State {
+                v: &[(0x1, 0x3C), (0x2, 0x0F), (0xF, 0x55)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x33), (0xF, 0x00)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY3 leaves VF",
+            code: &[0x8123],
+            quirks: &[("vf_reset", false)],
+            before: State {
+                v: &[(0x1, 0x3C), (0x2, 0x0F), (0xF, 0x55)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x33)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY4 without carry",
+            code: &[0x8124],
+            before: State {
+                v: &[(0x1, 0x10), (0x2, 0x20), (0xF, 0x55)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x30), (0xF, 0x00)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY4 with carry",
+            code: &[0x8124],
+            before: State {
+                v: &[(0x1, 0xF0), (0x2, 0x20)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x10), (0xF, 0x01)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8FY4 keeps the carry instead of the sum",
+            code: &[0x8F24],
+            before: State {
+                v: &[(0x2, 0x20), (0xF, 0xF0)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0xF, 0x01)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XF4 adds VF before it's overwritten",
+            code: &[0x81F4],
+            before: State {
+                v: &[(0x1, 0x10), (0xF, 0x20)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x30), (0xF, 0x00)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY5 without borrow",
+            code: &[0x8125],
+            before: State {
+                v: &[(0x1, 0x30), (0x2, 0x10)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x20), (0xF, 0x01)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY5 with borrow",
+            code: &[0x8125],
+            before: State {
+                v: &[(0x1, 0x10), (0x2, 0x20), (0xF, 0x55)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0xF0), (0xF, 0x00)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY5 of equal values doesn't borrow",
+            code: &[0x8125],
+            before: State {
+                v: &[(0x1, 0x10), (0x2, 0x10)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x00), (0xF, 0x01)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8FY5 keeps the flag instead of the difference",
+            code: &[0x8F25],
+            before: State {
+                v: &[(0x2, 0x10), (0xF, 0x30)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0xF, 0x01)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY6 shifts VY into VX",
+            code: &[0x8126],
+            quirks: &[("shift", false)],
+            before: State {
+                v: &[(0x1, 0xFF), (0x2, 0x81)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x40), (0xF, 0x01)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY6 shifts VX in place",
+            code: &[0x8126],
+            quirks: &[("shift", true)],
+            before: State {
+                v: &[(0x1, 0x02), (0x2, 0x81), (0xF, 0x55)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x01), (0xF, 0x00)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8FY6 keeps the flag instead of the result",
+            code: &[0x8F26],
+            quirks: &[("shift", false)],
+            before: State {
+                v: &[(0x2, 0x81)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0xF, 0x01)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY7 without borrow",
+            code: &[0x8127],
+            before: State {
+                v: &[(0x1, 0x10), (0x2, 0x30)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x20), (0xF, 0x01)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XY7 with borrow",
+            code: &[0x8127],
+            before: State {
+                v: &[(0x1, 0x30), (0x2, 0x10), (0xF, 0x55)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0xE0), (0xF, 0x00)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8FY7 keeps the flag instead of the difference",
+            code: &[0x8F27],
+            before: State {
+                v: &[(0x2, 0x30), (0xF, 0x10)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0xF, 0x01)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XYE shifts VY into VX",
+            code: &[0x812E],
+            quirks: &[("shift", false)],
+            before: State {
+                v: &[(0x1, 0xFF), (0x2, 0x81)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x02), (0xF, 0x01)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8XYE shifts VX in place",
+            code: &[0x812E],
+            quirks: &[("shift", true)],
+            before: State {
+                v: &[(0x1, 0x41), (0x2, 0x81), (0xF, 0x55)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x82), (0xF, 0x00)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "8FYE keeps the flag instead of the result",
+            code: &[0x8F2E],
+            quirks: &[("shift", false)],
+            before: State {
+                v: &[(0x2, 0x41)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0xF, 0x00)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "ANNN loads I",
+            code: &[0xA123],
+            after: State {
+                i: Some(0x123),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "CXNN masks the random byte",
+            code: &[0xC10F],
+            after: State {
+                v: &[(0x1, 0x05)],
+                ..SAME
+            },
+            ..CASE
+        },
+    ]);
+}
+
+#[test]
+fn keys_and_timers() {
+    run(&[
+        Case {
+            name: "EX9E skips when the key is down",
+            code: &[0xE19E],
+            before: State {
+                v: &[(0x1, 0x5)],
+                keys: &[0x5],
+                ..SAME
+            },
+            after: State {
+                pc: Some(0x204),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "EX9E doesn't skip when the key is up",
+            code: &[0xE19E],
+            before: State {
+                v: &[(0x1, 0x5)],
+                keys: &[0x6],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "EXA1 skips when the key is up",
+            code: &[0xE1A1],
+            before: State {
+                v: &[(0x1, 0x5)],
+                keys: &[0x6],
+                ..SAME
+            },
+            after: State {
+                pc: Some(0x204),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "EXA1 doesn't skip when the key is down",
+            code: &[0xE1A1],
+            before: State {
+                v: &[(0x1, 0x5)],
+                keys: &[0x5],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "EX9E with a key that doesn't exist",
+            code: &[0xE19E],
+            before: State {
+                v: &[(0x1, 0x10)],
+                ..SAME
+            },
+            outcome: Err(CpuError::InvalidKey {
+                pc: 0x200,
+                key: 0x10,
+            }),
+            ..CASE
+        },
+        Case {
+            name: "FX07 reads the delay timer",
+            code: &[0xF107],
+            before: State {
+                delay_timer: Some(0x33),
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x33)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX0A waits for a key",
+            code: &[0xF10A],
+            outcome: Ok(StepOutcome::WaitingForKey),
+            ..CASE
+        },
+        Case {
+            name: "FX0A stores the key",
+            code: &[0xF10A],
+            before: State {
+                keys: &[0x7],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 0x7)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX15 sets the delay timer",
+            code: &[0xF115],
+            before: State {
+                v: &[(0x1, 0x33)],
+                ..SAME
+            },
+            after: State {
+                delay_timer: Some(0x33),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX18 sets the sound timer",
+            code: &[0xF118],
+            before: State {
+                v: &[(0x1, 0x33)],
+                ..SAME
+            },
+            after: State {
+                sound_timer: Some(0x33),
+                ..SAME
+            },
+            ..CASE
+        },
+    ]);
+}
+
+#[test]
+fn index_and_memory() {
+    run(&[
+        Case {
+            name: "FX1E adds to I without touching VF",
+            code: &[0xF11E],
+            before: State {
+                v: &[(0x1, 0x10), (0xF, 0x55)],
+                i: Some(0x100),
+                ..SAME
+            },
+            after: State {
+                i: Some(0x110),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX1E wraps I",
+            code: &[0xF11E],
+            before: State {
+                v: &[(0x1, 0x02)],
+                i: Some(0xFFFF),
+                ..SAME
+            },
+            after: State {
+                i: Some(0x0001),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX29 points I at the digit",
+            code: &[0xF129],
+            before: State {
+                v: &[(0x1, 0x1A)],
+                ..SAME
+            },
+            after: State {
+                i: Some(0x32),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX33 stores the decimal digits",
+            code: &[0xF133],
+            before: State {
+                v: &[(0x1, 234)],
+                i: Some(0x300),
+                ..SAME
+            },
+            after: State {
+                memory: &[(0x300, &[2, 3, 4])],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX33 past the end of memory",
+            code: &[0xF133],
+            before: State {
+                i: Some(0xFFFE),
+                ..SAME
+            },
+            outcome: Err(CpuError::MemoryOutOfBounds {
+                pc: 0x200,
+                addr: 0x10000,
+            }),
+            ..CASE
+        },
+        Case {
+            name: "FX55 stores and moves I",
+            code: &[0xF255],
+            load_store: Some(LoadStore::Increment),
+            before: State {
+                v: &[(0x0, 1), (0x1, 2), (0x2, 3), (0x3, 4)],
+                i: Some(0x300),
+                ..SAME
+            },
+            after: State {
+                memory: &[(0x300, &[1, 2, 3])],
+                i: Some(0x303),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX55 stores and leaves I",
+            code: &[0xF255],
+            load_store: Some(LoadStore::Unchanged),
+            before: State {
+                v: &[(0x0, 1), (0x1, 2), (0x2, 3), (0x3, 4)],
+                i: Some(0x300),
+                ..SAME
+            },
+            after: State {
+                memory: &[(0x300, &[1, 2, 3])],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX55 stores and moves I to the last register",
+            code: &[0xF255],
+            load_store: Some(LoadStore::IncrementX),
+            before: State {
+                v: &[(0x0, 1), (0x1, 2), (0x2, 3), (0x3, 4)],
+                i: Some(0x300),
+                ..SAME
+            },
+            after: State {
+                memory: &[(0x300, &[1, 2, 3])],
+                i: Some(0x302),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX55 up to the last byte wraps I",
+            code: &[0xF255],
+            load_store: Some(LoadStore::Increment),
+            before: State {
+                v: &[(0x0, 1), (0x1, 2), (0x2, 3)],
+                i: Some(0xFFFD),
+                ..SAME
+            },
+            after: State {
+                memory: &[(0xFFFD, &[1, 2, 3])],
+                i: Some(0x0000),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX55 past the end of memory",
+            code: &[0xF255],
+            before: State {
+                i: Some(0xFFFE),
+                ..SAME
+            },
+            outcome: Err(CpuError::MemoryOutOfBounds {
+                pc: 0x200,
+                addr: 0x10000,
+            }),
+            ..CASE
+        },
+        Case {
+            name: "FX65 loads and moves I",
+            code: &[0xF265],
+            load_store: Some(LoadStore::Increment),
+            before: State {
+                memory: &[(0x300, &[1, 2, 3, 4])],
+                i: Some(0x300),
+                ..SAME
+            },
+            after: State {
+                v: &[(0x0, 1), (0x1, 2), (0x2, 3)],
+                i: Some(0x303),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX65 loads and moves I to the last register",
+            code: &[0xF265],
+            load_store: Some(LoadStore::IncrementX),
+            before: State {
+                memory: &[(0x300, &[1, 2, 3, 4])],
+                i: Some(0x300),
+                ..SAME
+            },
+            after: State {
+                v: &[(0x0, 1), (0x1, 2), (0x2, 3)],
+                i: Some(0x302),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX65 loads and leaves I",
+            code: &[0xF265],
+            load_store: Some(LoadStore::Unchanged),
+            before: State {
+                memory: &[(0x300, &[1, 2, 3, 4])],
+                i: Some(0x300),
+                ..SAME
+            },
+            after: State {
+                v: &[(0x0, 1), (0x1, 2), (0x2, 3)],
+                ..SAME
+            },
+            ..CASE
+        },
+    ]);
+}
+
+#[test]
+fn display() {
+    run(&[
+        Case {
+            name: "00E0 clears the screen",
+            code: &[0x00E0],
+            before: State {
+                screen: Some(&[(0, 0, "#.#"), (5, 20, "##")]),
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN draws and resets VF",
+            code: &[0xD122],
+            quirks: &[("display_wait", false)],
+            before: State {
+                v: &[(0x1, 1), (0x2, 2), (0xF, 0x55)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0xF0, 0x90])],
+                ..SAME
+            },
+            after: State {
+                v: &[(0xF, 0)],
+                screen: Some(&[(1, 2, "####"), (1, 3, "#..#")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN erases and sets VF",
+            code: &[0xD122],
+            quirks: &[("display_wait", false)],
+            before: State {
+                v: &[(0x1, 1), (0x2, 2)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0xF0, 0x90])],
+                screen: Some(&[(1, 2, "#"), (0, 3, "#")]),
+                ..SAME
+            },
+            after: State {
+                v: &[(0xF, 1)],
+                screen: Some(&[(2, 2, "###"), (0, 3, "##..#")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN waits for the vblank",
+            code: &[0xD122],
+            quirks: &[("display_wait", true)],
+            before: State {
+                v: &[(0x1, 1), (0x2, 2)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0xF0, 0x90])],
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(1, 2, "####"), (1, 3, "#..#")]),
+                vblank_wait: Some(true),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "nothing runs before the vblank",
+            code: &[0xD122],
+            before: State {
+                v: &[(0x1, 1), (0x2, 2)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0xF0, 0x90])],
+                vblank_wait: Some(true),
+                ..SAME
+            },
+            outcome: Ok(StepOutcome::WaitingForVblank),
+            ..CASE
+        },
+        Case {
+            name: "DXYN wraps the starting position",
+            code: &[0xD122],
+            quirks: &[("display_wait", false)],
+            before: State {
+                v: &[(0x1, 65), (0x2, 34)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0xF0, 0x90])],
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(1, 2, "####"), (1, 3, "#..#")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN clips at the right edge",
+            code: &[0xD121],
+            quirks: &[("clip", true), ("display_wait", false)],
+            before: State {
+                v: &[(0x1, 62)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0xFF])],
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(62, 0, "##")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN wraps at the right edge",
+            code: &[0xD121],
+            quirks: &[("clip", false), ("display_wait", false)],
+            before: State {
+                v: &[(0x1, 62)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0xFF])],
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(62, 0, "##"), (0, 0, "######")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN clips at the bottom edge",
+            code: &[0xD122],
+            quirks: &[("clip", true), ("display_wait", false)],
+            before: State {
+                v: &[(0x2, 31)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0x80, 0xC0])],
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(0, 31, "#")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN wraps at the bottom edge",
+            code: &[0xD122],
+            quirks: &[("clip", false), ("display_wait", false)],
+            before: State {
+                v: &[(0x2, 31)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0x80, 0xC0])],
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(0, 31, "#"), (0, 0, "##")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN collides with wrapped pixels",
+            code: &[0xD121],
+            quirks: &[("clip", false), ("display_wait", false)],
+            before: State {
+                v: &[(0x1, 62)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0xFF])],
+                screen: Some(&[(0, 0, "#")]),
+                ..SAME
+            },
+            after: State {
+                v: &[(0xF, 1)],
+                screen: Some(&[(62, 0, "##"), (0, 0, ".#####")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN doesn't collide with clipped pixels",
+            code: &[0xD121],
+            quirks: &[("clip", true), ("display_wait", false)],
+            before: State {
+                v: &[(0x1, 62), (0xF, 1)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0xFF])],
+                screen: Some(&[(0, 0, "#")]),
+                ..SAME
+            },
+            after: State {
+                v: &[(0xF, 0)],
+                screen: Some(&[(0, 0, "#"), (62, 0, "##")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN wraps past the corner",
+            code: &[0xD122],
+            quirks: &[("clip", false), ("display_wait", false)],
+            before: State {
+                v: &[(0x1, 62), (0x2, 31)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0xF0, 0xF0])],
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(62, 31, "##"), (0, 31, "##"), (62, 0, "##"), (0, 0, "##")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN clips past the corner",
+            code: &[0xD122],
+            quirks: &[("clip", true), ("display_wait", false)],
+            before: State {
+                v: &[(0x1, 62), (0x2, 31)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0xF0, 0xF0])],
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(62, 31, "##")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXY0 wraps at the right edge in hires",
+            code: &[0xD120],
+            quirks: &[("clip", false), ("display_wait", false)],
+            before: State {
+                v: &[(0x1, 120)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0xFF, 0xFF])],
+                hires: Some(true),
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(120, 0, "########"), (0, 0, "########")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXY0 draws 16x16",
+            code: &[0xD120],
+            quirks: &[("display_wait", false)],
+            before: State {
+                i: Some(0x300),
+                memory: &[(0x300, &[0xFF, 0xFF, 0x80, 0x01])],
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(0, 0, "################"), (0, 1, "#..............#")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN in hires",
+            code: &[0xD121],
+            quirks: &[("display_wait", false)],
+            before: State {
+                v: &[(0x1, 100), (0x2, 60)],
+                i: Some(0x300),
+                memory: &[(0x300, &[0x80])],
+                hires: Some(true),
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(100, 60, "#")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN with the sprite past the end of memory",
+            code: &[0xD122],
+            before: State {
+                i: Some(0xFFFF),
+                ..SAME
+            },
+            outcome: Err(CpuError::MemoryOutOfBounds {
+                pc: 0x200,
+                addr: 0x10000,
+            }),
+            ..CASE
+        },
+    ]);
+}
+
+#[test]
+fn super_chip() {
+    run(&[
+        Case {
+            name: "00CN scrolls down",
+            code: &[0x00C2],
+            before: State {
+                screen: Some(&[(0, 0, "#")]),
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(0, 2, "#")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "00FB scrolls right",
+            code: &[0x00FB],
+            before: State {
+                screen: Some(&[(0, 0, "#"), (63, 1, "#")]),
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(4, 0, "#")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "00FC scrolls left",
+            code: &[0x00FC],
+            before: State {
+                screen: Some(&[(1, 0, "#"), (4, 1, "#")]),
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(0, 1, "#")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "00FD exits",
+            code: &[0x00FD],
+            outcome: Ok(StepOutcome::Exited),
+            ..CASE
+        },
+        Case {
+            name: "00FE goes to lores and clears",
+            code: &[0x00FE],
+            before: State {
+                hires: Some(true),
+                screen: Some(&[(100, 60, "#")]),
+                ..SAME
+            },
+            after: State {
+                hires: Some(false),
+                screen: Some(&[]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "00FF goes to hires and clears",
+            code: &[0x00FF],
+            before: State {
+                screen: Some(&[(10, 10, "#")]),
+                ..SAME
+            },
+            after: State {
+                hires: Some(true),
+                screen: Some(&[]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX30 points I at the big digit",
+            code: &[0xF130],
+            before: State {
+                v: &[(0x1, 3)],
+                ..SAME
+            },
+            after: State {
+                i: Some(0x6E),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX30 points I at the big hex digits too",
+            code: &[0xF130],
+            before: State {
+                v: &[(0x1, 0x1F)],
+                ..SAME
+            },
+            after: State {
+                i: Some(0xE6),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX75 saves the flags",
+            code: &[0xF275],
+            before: State {
+                v: &[(0x0, 1), (0x1, 2), (0x2, 3), (0x3, 4)],
+                ..SAME
+            },
+            after: State {
+                rpl: &[(0, 1), (1, 2), (2, 3)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX85 loads the flags",
+            code: &[0xF285],
+            before: State {
+                rpl: &[(0, 1), (1, 2), (2, 3), (3, 4)],
+                ..SAME
+            },
+            after: State {
+                v: &[(0x0, 1), (0x1, 2), (0x2, 3)],
+                ..SAME
+            },
+            ..CASE
+        },
+    ]);
+}
+
+#[test]
+fn xo_chip() {
+    run(&[
+        Case {
+            name: "5XY2 saves a range",
+            code: &[0x5132],
+            before: State {
+                v: &[(0x1, 1), (0x2, 2), (0x3, 3)],
+                i: Some(0x300),
+                ..SAME
+            },
+            after: State {
+                memory: &[(0x300, &[1, 2, 3])],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "5XY2 saves a range backwards",
+            code: &[0x5312],
+            before: State {
+                v: &[(0x1, 1), (0x2, 2), (0x3, 3)],
+                i: Some(0x300),
+                ..SAME
+            },
+            after: State {
+                memory: &[(0x300, &[3, 2, 1])],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "5XY3 loads a range",
+            code: &[0x5133],
+            before: State {
+                memory: &[(0x300, &[1, 2, 3])],
+                i: Some(0x300),
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 1), (0x2, 2), (0x3, 3)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "5XY3 loads a range backwards",
+            code: &[0x5313],
+            before: State {
+                memory: &[(0x300, &[1, 2, 3])],
+                i: Some(0x300),
+                ..SAME
+            },
+            after: State {
+                v: &[(0x1, 3), (0x2, 2), (0x3, 1)],
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "F000 NNNN loads a long I",
+            code: &[0xF000, 0xABCD],
+            after: State {
+                i: Some(0xABCD),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FN01 selects planes",
+            code: &[0xF201],
+            after: State {
+                plane: Some(2),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "00E0 clears the selected plane",
+            code: &[0x00E0],
+            before: State {
+                plane: Some(2),
+                screen: Some(&[(0, 0, "#+*")]),
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(0, 0, "#.#")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "DXYN draws one sprite per plane",
+            code: &[0xD001],
+            quirks: &[("display_wait", false)],
+            before: State {
+                plane: Some(3),
+                i: Some(0x300),
+                memory: &[(0x300, &[0x80, 0xC0])],
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(0, 0, "*+")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "00DN scrolls the selected plane up",
+            code: &[0x00D2],
+            before: State {
+                plane: Some(2),
+                screen: Some(&[(0, 3, "*")]),
+                ..SAME
+            },
+            after: State {
+                screen: Some(&[(0, 3, "#"), (0, 1, "+")]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "F002 loads the audio pattern",
+            code: &[0xF002],
+            before: State {
+                i: Some(0x300),
+                memory: &[(
+                    0x300,
+                    &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+                )],
+                ..SAME
+            },
+            after: State {
+                pattern: Some([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]),
+                ..SAME
+            },
+            ..CASE
+        },
+        Case {
+            name: "FX3A sets the pitch",
+            code: &[0xF13A],
+            before: State {
+                v: &[(0x1, 0x70)],
+                ..SAME
+            },
+            after: State {
+                pitch: Some(0x70),
+                ..SAME
+            },
+            ..CASE
+        },
+    ]);
+}
+
+#[test]
+fn load_rom() {
+    let mut cpu: TestCpu = with_random(Quirks::default(), random as fn() -> u8);
+    let rom = [0xAA; MAX_ROM_SIZE + 1];
+    assert_eq!(cpu.load_rom(&rom[..MAX_ROM_SIZE]), Ok(()));
+    assert_eq!(cpu.memory[0xFFFF], 0xAA);
+    // too big for memory, nothing is loaded
+    let mut other: TestCpu = with_random(Quirks::default(), random as fn() -> u8);
+    assert_eq!(
+        other.load_rom(&rom),
+        Err(RomError::TooLarge {
+            size: MAX_ROM_SIZE + 1
+        })
+    );
+    assert_eq!(other.memory[0x200], 0);
+}
+
+#[test]
+fn big_font() {
+    let cpu: TestCpu = with_random(Quirks::default(), random as fn() -> u8);
+    let glyph = |digit: usize| &cpu.memory[0x50 + digit * 10..0x5A + digit * 10];
+    // every hex digit has a glyph of its own
+    for digit in 0..16 {
+        assert!(
+            glyph(digit).iter().any(|&row| row != 0),
+            "{:X} is blank",
+            digit
+        );
+        for other in 0..digit {
+            assert_ne!(glyph(digit), glyph(other), "{:X} and {:X}", other, digit);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+// COSMAC VIP timing: roughly what each instruction costs the original
+// interpreter in 1802 machine cycles (8 clock cycles, about 4.54µs at the
+// VIP's 1.7609 MHz), fetch and decode included. Counting them instead of
+// instructions is what lets Speed::CosmacVip run a program about as fast as a
+// VIP did
+//
+// These are approximations, not measurements: rounded estimates of how long
+// the interpreter's routines run, good enough to get game speed and the
+// balance between cheap and expensive instructions right but not to be cycle
+// exact
+// Most instructions always take the same time, skips take a little longer
+// when they skip and the ones looping over memory grow with how much of it
+// they touch. DXYN is the expensive one: the VIP shifts every sprite row
+// into place one bit at a time, so a sprite costs more the further x is from
+// a multiple of 8, and an unaligned row has a second byte to XOR. Instructions
+// the VIP never had cost what their closest VIP relative does
+use super::{Cpu, Instruction, Random};
+
+// 1861 interrupts, one a frame, are this far apart: 1.7609 MHz / 8 / 60
+pub const VIP_FRAME_CYCLES: u32 = 3668;
+// Of which the display DMA and the interrupt routine, which also counts the
+// timers down, take about this many. The interpreter runs in the rest
+pub const VIP_INTERRUPT_CYCLES: u32 = 1832;
+
+// Fetching two bytes, moving pc on and jumping through the dispatch table
+const FETCH: u32 = 20;
+// Extra for a skip that skips
+const SKIP: u32 = 4;
+
+impl<R: Random> Cpu<R> {
+    // Machine cycles of the instructions run so far, as the VIP would have
+    // spent them
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // The estimated cost of running instruction in the state the Cpu is in now
+    pub(super) fn vip_cycles(&self, instruction: Instruction) -> u32 {
+        use self::Instruction::*;
+
+        let v = |x: u8| self.v[x as usize];
+        let pressed = |x: u8| {
+            self.keyboard
+                .get(v(x) as usize)
+                .is_some_and(|&key| key != 0)
+        };
+        let skip = |skips: bool| if skips { SKIP } else { 0 };
+        let cost = match instruction {
+            Cls => 24 + 4 * 256,
+            Ret => 10,
+            Sys { .. } | Jp { .. } | LdI { .. } | LdILong { .. } => 12,
+            Call { .. } => 26,
+            JpV0 { .. } => 22,
+            SeByte { x, nn } => 10 + skip(v(x) == nn),
+            SneByte { x, nn } => 10 + skip(v(x) != nn),
+            SeReg { x, y } => 14 + skip(v(x) == v(y)),
+            SneReg { x, y } => 14 + skip(v(x) != v(y)),
+            Skp { x } => 14 + skip(pressed(x)),
+            Sknp { x } => 14 + skip(!pressed(x)),
+            LdByte { .. } | LdVxDt { .. } | LdDtVx { .. } | LdStVx { .. } => 6,
+            AddByte { .. } | LdVxK { .. } => 10,
+            // the VIP builds the ALU instruction in memory and runs it
+            LdReg { .. }
+            | Or { .. }
+            | And { .. }
+            | Xor { .. }
+            | AddReg { .. }
+            | Sub { .. }
+            | Shr { .. }
+            | Subn { .. }
+            | Shl { .. } => 44,
+            Rnd { .. } => 36,
+            Drw { x, y, n } => self.draw_cycles(v(x), v(y), n),
+            AddIVx { .. } => 14,
+            LdFVx { .. } | LdHfVx { .. } => 16,
+            // one subtraction for every unit of every digit
+            LdBVx { x } => {
+                let value = u32::from(v(x));
+                40 + 8 * (value / 100 + value / 10 % 10 + value % 10)
+            }
+            LdIVx { x } | LdVxI { x } | LdRVx { x } | LdVxR { x } => 14 + 14 * (u32::from(x) + 1),
+            SaveRange { x, y } | LoadRange { x, y } => {
+                14 + 14 * (u32::from(x.max(y) - x.min(y)) + 1)
+            }
+            Scd { .. } | Scu { .. } | Scr | Scl => 24 + 4 * 256,
+            Exit | Low | High | Plane { .. } | Audio | Pitch { .. } => 6,
+        };
+        FETCH + cost
+    }
+
+    fn draw_cycles(&self, x: u8, y: u8, n: u8) -> u32 {
+        let (bytes, rows) = if n == 0 { (2, 16) } else { (1, usize::from(n)) };
+        let shift = usize::from(x) % self.width() % 8;
+        // the VIP doesn't draw the rows below the screen
+        let rows = if self.quirks.clip {
+            rows.min(self.height() - usize::from(y) % self.height())
+        } else {
+            rows
+        };
+        let row = 18 + 8 * shift as u32 + if shift != 0 { 16 } else { 0 };
+        let planes = self.plane.count_ones().max(1);
+        26 + rows as u32 * row * bytes * planes
+    }
+}
@@ -0,0 +1,236 @@
+// Execution traces, one line for every instruction that ran:
+//
+//   12 0206 F055 I=020B V0=05 [020A]=05 ; LD [I], V0
+//
+// is the cycle (instructions since the trace started), pc and opcode, I
+// after the instruction, the V registers and timers it changed, the bytes it
+// wrote and after the ; the mnemonic. Unlike the debug! logging it's compact,
+// costs nothing while off and parses back into TraceRecords, which is how
+// chip8-tracediff finds where two runs went different ways
+use std::fmt;
+use std::io::{self, Write};
+
+use super::{Access, Cpu, CpuError, Instruction, Random, StepOutcome};
+
+pub struct Trace {
+    out: Box<dyn Write + Send>,
+    cycle: u64,
+    // the first write that failed, nothing is written after it
+    error: Option<io::Error>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub pc: u16,
+    // 8 digits for F000 NNNN
+    pub opcode: u32,
+    pub i: u16,
+    // (x, value) of the V registers that changed
+    pub v: Vec<(u8, u8)>,
+    pub delay_timer: Option<u8>,
+    pub sound_timer: Option<u8>,
+    // (address, value) of every byte written
+    pub writes: Vec<(u16, u8)>,
+    pub mnemonic: String,
+}
+
+impl<R: Random> Cpu<R> {
+    // Record every instruction from now on, replacing the trace already
+    // running if there's one. There's a write per instruction so files are
+    // better wrapped in a BufWriter
+    pub fn start_trace<W: Write + Send + 'static>(&mut self, out: W) {
+        self.trace = Some(Trace {
+            out: Box::new(out),
+            cycle: 0,
+            error: None,
+        });
+    }
+
+    // Stop and flush the trace, the error is the first one writing it
+    pub fn stop_trace(&mut self) -> io::Result<()> {
+        match self.trace.take() {
+            Some(Trace {
+                error: Some(err), ..
+            }) => Err(err),
+            Some(mut trace) => trace.out.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    // execute_checked, recording what the instruction did
+    pub(super) fn execute_traced(
+        &mut self,
+        instruction: Instruction,
+    ) -> Result<StepOutcome, CpuError> {
+        let pc = self.opcode_pc;
+        let v = self.v;
+        let delay_timer = self.delay_timer;
+        let sound_timer = self.sound_timer;
+        let written = match self.effects(instruction).memory {
+            Some((Access::Write, start, len)) => start as usize..(start + len) as usize,
+            _ => 0..0,
+        };
+
+        let outcome = self.execute_checked(instruction)?;
+        let ran = match outcome {
+            StepOutcome::Executed | StepOutcome::Exited => true,
+            StepOutcome::Break(hit) => !hit.breakpoint.before(),
+            StepOutcome::WaitingForKey | StepOutcome::WaitingForVblank => false,
+        };
+        let cycle = match self.trace {
+            Some(ref trace) if ran && trace.error.is_none() => trace.cycle,
+            _ => return Ok(outcome),
+        };
+
+        let opcode = match instruction {
+            Instruction::LdILong { nnnn } => 0xF000_0000 | u32::from(nnnn),
+            _ => u32::from(instruction.encode()),
+        };
+        let changed = |before: u8, after: u8| if before != after { Some(after) } else { None };
+        let record = TraceRecord {
+            cycle,
+            pc,
+            opcode,
+            i: self.i,
+            v: (0..16)
+                .filter(|&x| v[x] != self.v[x])
+                .map(|x| (x as u8, self.v[x]))
+                .collect(),
+            delay_timer: changed(delay_timer, self.delay_timer),
+            sound_timer: changed(sound_timer, self.sound_timer),
+            writes: written
+                .filter(|&addr| addr < self.memory.len())
+                .map(|addr| (addr as u16, self.memory[addr]))
+                .collect(),
+            mnemonic: instruction.to_string(),
+        };
+        if let Some(ref mut trace) = self.trace {
+            trace.cycle += 1;
+            if let Err(err) = writeln!(trace.out, "{}", record) {
+                trace.error = Some(err);
+            }
+        }
+        Ok(outcome)
+    }
+}
+
+impl TraceRecord {
+    // A line of a trace, None if it isn't one
+    pub fn parse(line: &str) -> Option<TraceRecord> {
+        let (fields, mnemonic) = match line.find(" ; ") {
+            Some(n) => (&line[..n], line[n + 3..].trim()),
+            None => (line, ""),
+        };
+        let mut fields = fields.split_whitespace();
+        let mut record = TraceRecord {
+            cycle: fields.next()?.parse().ok()?,
+            pc: u16::from_str_radix(fields.next()?, 16).ok()?,
+            opcode: u32::from_str_radix(fields.next()?, 16).ok()?,
+            i: 0,
+            v: Vec::new(),
+            delay_timer: None,
+            sound_timer: None,
+            writes: Vec::new(),
+            mnemonic: mnemonic.to_string(),
+        };
+        for field in fields {
+            let (name, value) = field.split_once('=')?;
+            let byte = || u8::from_str_radix(value, 16).ok();
+            match name {
+                "I" => record.i = u16::from_str_radix(value, 16).ok()?,
+                "DT" => record.delay_timer = Some(byte()?),
+                "ST" => record.sound_timer = Some(byte()?),
+                _ if name.len() == 2 && name.starts_with('V') => {
+                    let x = u8::from_str_radix(&name[1..], 16).ok()?;
+                    record.v.push((x, byte()?));
+                }
+                _ if name.starts_with('[') && name.ends_with(']') => {
+                    let addr = u16::from_str_radix(&name[1..name.len() - 1], 16).ok()?;
+                    record.writes.push((addr, byte()?));
+                }
+                _ => return None,
+            }
+        }
+        Some(record)
+    }
+
+    // What's different from the record of another run, ignoring the cycle and
+    // the mnemonic. "-" is a register that didn't change
+    pub fn differences(&self, other: &TraceRecord) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |name: &str, a: String, b: String| {
+            if a != b {
+                differences.push(format!("{}: {} vs {}", name, a, b));
+            }
+        };
+        compare(
+            "pc",
+            format!("{:04X}", self.pc),
+            format!("{:04X}", other.pc),
+        );
+        compare("opcode", opcode(self.opcode), opcode(other.opcode));
+        compare("I", format!("{:04X}", self.i), format!("{:04X}", other.i));
+        for x in 0..16 {
+            let value = |record: &TraceRecord| {
+                let value = record.v.iter().find(|&&(y, _)| y == x);
+                byte(value.map(|&(_, value)| value))
+            };
+            compare(&format!("V{:X}", x), value(self), value(other));
+        }
+        compare("DT", byte(self.delay_timer), byte(other.delay_timer));
+        compare("ST", byte(self.sound_timer), byte(other.sound_timer));
+        let writes = |record: &TraceRecord| {
+            let writes: Vec<String> = record
+                .writes
+                .iter()
+                .map(|&(addr, value)| format!("[{:04X}]={:02X}", addr, value))
+                .collect();
+            writes.join(" ")
+        };
+        compare("writes", writes(self), writes(other));
+        differences
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:04X} {} I={:04X}",
+            self.cycle,
+            self.pc,
+            opcode(self.opcode),
+            self.i
+        )?;
+        for &(x, value) in &self.v {
+            write!(f, " V{:X}={:02X}", x, value)?;
+        }
+        if let Some(value) = self.delay_timer {
+            write!(f, " DT={:02X}", value)?;
+        }
+        if let Some(value) = self.sound_timer {
+            write!(f, " ST={:02X}", value)?;
+        }
+        for &(addr, value) in &self.writes {
+            write!(f, " [{:04X}]={:02X}", addr, value)?;
+        }
+        write!(f, " ; {}", self.mnemonic)
+    }
+}
+
+fn opcode(opcode: u32) -> String {
+    if opcode > 0xFFFF {
+        format!("{:08X}", opcode)
+    } else {
+        format!("{:04X}", opcode)
+    }
+}
+
+fn byte(value: Option<u8>) -> String {
+    value.map_or("-".to_string(), |value| format!("{:02X}", value))
+}
@@ -0,0 +1,37 @@
+// CHIP-8, SUPER-CHIP and XO-CHIP interpreter core
+// It knows nothing about windows or sound, frontends feed it key presses and
+// timer ticks and read back the display and the sound flag
+// Without the std feature it builds for bare-metal targets: no allocations,
+// the random source is injected and logging goes through the log facade (or
+// nowhere without the log feature)
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+
+#[cfg(not(feature = "log"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {};
+}
+
+mod chip8;
+
+pub use chip8::{new, with_random};
+pub use chip8::{
+    Cpu, CpuError, Instruction, LoadStore, Quirks, Random, RomError, StepOutcome, XorShift,
+};
+pub use chip8::MAX_ROM_SIZE;
+pub use chip8::{Scheduler, Speed, StateError, STATE_SIZE, VIP_FRAME_CYCLES, VIP_INTERRUPT_CYCLES};
+pub use chip8::{Access, Breakpoint, BreakpointHit, Comparison, Condition, Register, MAX_BREAKPOINTS};
+#[cfg(feature = "std")]
+pub use chip8::{assemble, assemble_file, assemble_octo, assemble_octo_file};
+#[cfg(feature = "std")]
+pub use chip8::{AsmError, Assembly, Listing};
+#[cfg(feature = "std")]
+pub use chip8::{Disassembly, Line, Rewind};
+#[cfg(feature = "std")]
+pub use chip8::{serve_gdb, TraceRecord};
@@ -0,0 +1,290 @@
+extern crate chip8;
+
+use std::env;
+use std::fs;
+
+use chip8::{assemble, assemble_file, assemble_octo, Disassembly, Instruction, Line};
+
+const PROGRAM: &str = "
+; draw a sprite where the keypad says
+HEIGHT  EQU 2
+start:  LD V0, K
+        LD I, sprite
+        DRW V0, V0, HEIGHT
+        JP start
+sprite: DB 0b11110000, 0x90   ; forward reference above
+";
+
+#[test]
+fn assembles_a_program() {
+    let assembly = assemble(PROGRAM).unwrap();
+    assert_eq!(
+        assembly.rom,
+        [0xF0, 0x0A, 0xA2, 0x08, 0xD0, 0x02, 0x12, 0x00, 0xF0, 0x90]
+    );
+    // and it's what the disassembler traces back
+    let disassembly = Disassembly::new(&assembly.rom);
+    assert_eq!(
+        disassembly.lines()[1],
+        Line::Code {
+            addr: 0x202,
+            instruction: Instruction::LdI { nnn: 0x208 },
+        }
+    );
+}
+
+#[test]
+fn every_instruction() {
+    let source = "
+        SYS 0x123
+        SCD 4
+        SCU 4
+        CLS
+        RET
+        SCR
+        SCL
+        EXIT
+        LOW
+        HIGH
+        JP 0x234
+        JP V0, 0x234
+        CALL 0x345
+        SE V1, 0x12
+        SE V1, V2
+        SNE V1, 0x12
+        SNE V1, V2
+        SAVE V1, V2
+        LOAD V1, V2
+        ld v1, -1
+        add v1, 2
+        ld v1, v2
+        or v1, v2
+        and v1, v2
+        xor v1, v2
+        add v1, v2
+        sub v1, v2
+        shr v1
+        subn v1, v2
+        shl v1, v2
+        ld i, 0x456
+        rnd vf, 0x0f
+        drw va, vb, 15
+        skp v3
+        sknp v3
+        ld i, long 0x1234
+        plane 3
+        audio
+        ld v4, dt
+        ld v4, k
+        ld dt, v4
+        ld st, v4
+        add i, v4
+        ld f, v4
+        ld hf, v4
+        ld b, v4
+        pitch v4
+        ld [i], v4
+        ld v4, [i]
+        ld r, v4
+        ld v4, r
+    ";
+    let expected: [u16; 52] = [
+        0x0123, 0x00C4, 0x00D4, 0x00E0, 0x00EE, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF, 0x1234,
+        0xB234, 0x2345, 0x3112, 0x5120, 0x4112, 0x9120, 0x5122, 0x5123, 0x61FF, 0x7102, 0x8120,
+        0x8121, 0x8122, 0x8123, 0x8124, 0x8125, 0x8116, 0x8127, 0x812E, 0xA456, 0xCF0F, 0xDABF,
+        0xE39E, 0xE3A1, 0xF000, 0x1234, 0xF301, 0xF002, 0xF407, 0xF40A, 0xF415, 0xF418, 0xF41E,
+        0xF429, 0xF430, 0xF433, 0xF43A, 0xF455, 0xF465, 0xF475, 0xF485,
+    ];
+    let rom = assemble(source).unwrap().rom;
+    let words: Vec<u16> = rom
+        .chunks(2)
+        .map(|word| u16::from(word[0]) << 8 | u16::from(word[1]))
+        .collect();
+    assert_eq!(words, &expected[..]);
+}
+
+#[test]
+fn data_and_org() {
+    let source = "
+        DW 0xBEEF, end
+        DB \"A;B\", 1 + 2 * 3
+        ORG 0x208
+end:    DB -1
+    ";
+    assert_eq!(
+        assemble(source).unwrap().rom,
+        [0xBE, 0xEF, 0x02, 0x08, b'A', b';', b'B', 7, 0xFF]
+    );
+}
+
+#[test]
+fn errors_have_line_numbers() {
+    let error = |source: &str| {
+        let err = assemble(source).unwrap_err();
+        (err.line, err.message)
+    };
+    assert_eq!(
+        error("CLS\nFOO V1\n"),
+        (2, "unknown instruction `FOO`".to_string())
+    );
+    assert_eq!(
+        error("CLS\n\nJP nowhere\n"),
+        (3, "undefined symbol `nowhere`".to_string())
+    );
+    assert_eq!(error("LD V0, 256").0, 1);
+    assert_eq!(error("ADD V0, K").1, "invalid operands for ADD");
+    assert_eq!(
+        error("a: CLS\na: RET"),
+        (2, "`a` is already defined".to_string())
+    );
+    assert_eq!(error("DB 1\nORG 0x200\nDB 2").0, 3);
+    assert_eq!(
+        assemble("DRW V0, V1, 16").unwrap_err().to_string(),
+        "<source>:1: nibble 16 is out of range (0x10)"
+    );
+    // i64::MIN has no positive counterpart
+    assert_eq!(error("DW (1 << 63) / -1").1, "overflow");
+    assert_eq!(error("DW (1 << 63) % -1").1, "overflow");
+    assert_eq!(error("DW 1 / 0").1, "division by zero");
+    assert_eq!(
+        error("DW -(1 << 63)").1,
+        "word -(1 << 63) is out of range (0x8000000000000000)"
+    );
+}
+
+#[test]
+fn includes_and_listing() {
+    let dir = env::temp_dir().join(format!("chip8-asm-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.asm"), "CLS\nINCLUDE \"sprite.asm\"\nRET\n").unwrap();
+    fs::write(dir.join("sprite.asm"), "; a sprite\nDB 1, 2, 3, 4, 5\n").unwrap();
+
+    let assembly = assemble_file(dir.join("main.asm")).unwrap();
+    assert_eq!(assembly.rom, [0x00, 0xE0, 1, 2, 3, 4, 5, 0x00, 0xEE]);
+    let listing: Vec<(u16, usize)> = assembly
+        .listing
+        .iter()
+        .map(|entry| (entry.addr, entry.line))
+        .collect();
+    assert_eq!(listing, [(0x200, 1), (0x202, 2), (0x207, 3)]);
+    assert!(assembly.listing[1].file.ends_with("sprite.asm"));
+
+    let text = assembly.listing_text();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[1].starts_with("202  01 02 03 04  "));
+    assert!(lines[1].ends_with("sprite.asm:2  DB 1, 2, 3, 4, 5"));
+    assert_eq!(lines[2], "206  05");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn words(rom: &[u8]) -> Vec<u16> {
+    rom.chunks(2)
+        .map(|word| u16::from(word[0]) << 8 | u16::from(word[1]))
+        .collect()
+}
+
+#[test]
+fn octo_program() {
+    let source = "
+        :const SPEED 3
+        :alias x v1
+        :macro step reg { reg += SPEED }
+        : main
+            i := smile      # forward reference
+            loop
+                sprite x v2 4
+                step x
+                if x == 60 then x := 0
+                while x != 30
+            again
+            i := long smile
+            :unpack 0xA smile
+        : smile 0x24 0x00 0x81 0x7E
+        :byte { SPEED << 2 }
+    ";
+    let rom = assemble_octo(source).unwrap().rom;
+    assert_eq!(
+        words(&rom[..0x1C]),
+        [
+            0xA218, 0xD124, 0x7103, 0x413C, 0x6100, 0x411E, 0x1210, 0x1202, 0xF000, 0x0218, 0x60A2,
+            0x6118, 0x2400, 0x817E,
+        ]
+    );
+    assert_eq!(rom[0x1C..], [0x0C]);
+}
+
+#[test]
+fn octo_conditions() {
+    let source = "
+        : main
+            if v1 < v2 begin
+                v3 := 1
+            else
+                v3 := 2
+            end
+            if v1 > 5 then v3 += -1
+            if v1 key then v3 -= 1
+            if v1 -key then v3 =- v4
+    ";
+    let rom = assemble_octo(source).unwrap().rom;
+    assert_eq!(
+        words(&rom),
+        [
+            // VF = V1 >= V2, skip the jump to else when it's 0
+            0x8F10, 0x8F25, 0x3F00, 0x120C, 0x6301, 0x120E, 0x6302,
+            // VF = 5 >= V1, run the add when it's 0
+            0x6F05, 0x8F15, 0x4F00, 0x73FF, 0xE1A1, 0x73FF, 0xE19E, 0x8347,
+        ]
+    );
+}
+
+#[test]
+fn octo_jumps_to_main() {
+    let rom = assemble_octo(": sub return\n: main sub ;").unwrap().rom;
+    assert_eq!(words(&rom), [0x1204, 0x00EE, 0x2202, 0x00EE]);
+}
+
+#[test]
+fn octo_errors() {
+    let error = |source: &str| assemble_octo(source).unwrap_err().to_string();
+    assert_eq!(
+        error(": main\n  jump nowhere\n"),
+        "<source>:2: undefined name `nowhere`"
+    );
+    assert_eq!(
+        error(": main\n  loop\n  v0 += 1\n"),
+        "<source>:2: `loop` without `again`"
+    );
+    assert_eq!(
+        error(": main v0 := 300"),
+        "<source>:1: byte 300 is out of range"
+    );
+    assert_eq!(
+        error(": main\n: main"),
+        "<source>:2: `main` is already defined"
+    );
+}
+
+#[test]
+fn octo_jumps_out_of_range() {
+    let error = |source: &str| assemble_octo(source).unwrap_err().to_string();
+    // JP only has 12 bits, 0x1000 would silently become 0x000
+    assert_eq!(
+        error(":org 0x1000 : main loop v0 += 1 again"),
+        "<source>:1: address 0x1000 is out of range"
+    );
+    assert_eq!(
+        error(": main\n  jump next\n:org 0x1000\n: next\n  loop\n  v0 += 1\n  again\n"),
+        "<source>:7: address 0x1000 is out of range"
+    );
+    assert_eq!(
+        error(": other\n:org 0x1000\n: main\n"),
+        "<source>:3: address 0x1000 is out of range"
+    );
+    assert_eq!(
+        error(": main\n  loop\n  while v0 != 0\n:org 0xFFE\n  v0 += 1\n  again\n"),
+        "<source>:3: address 0x1002 is out of range"
+    );
+}
@@ -0,0 +1,172 @@
+extern crate chip8;
+
+use chip8::{
+    Access, Breakpoint, BreakpointHit, Comparison, Condition, Cpu, Register, StepOutcome,
+    MAX_BREAKPOINTS,
+};
+
+// Counts V0 from 1 to 5 writing it as BCD to data, then waits for a key
+const PROGRAM: &str = "
+        LD V0, 0
+        LD I, data
+loop:   ADD V0, 1
+        LD B, V0
+        SE V0, 5
+        JP loop
+        LD V1, K
+end:    JP end
+data:   DB 0, 0, 0
+";
+const LOOP: u16 = 0x204;
+const STORE: u16 = 0x206;
+const WAIT: u16 = 0x20C;
+const DATA: u16 = 0x210;
+
+fn machine() -> Cpu {
+    let mut chip = chip8::new(chip8::Quirks::default());
+    chip.load_rom(&chip8::assemble(PROGRAM).unwrap().rom)
+        .unwrap();
+    chip
+}
+
+fn hit(chip: &mut Cpu) -> BreakpointHit {
+    match chip.run(1000) {
+        Ok(StepOutcome::Break(hit)) => hit,
+        outcome => panic!("expected a breakpoint, got {:?}", outcome),
+    }
+}
+
+fn v0(comparison: Comparison, value: u16) -> Condition {
+    Condition {
+        register: Register::V(0),
+        comparison,
+        value,
+    }
+}
+
+#[test]
+fn pc_breaks_before_the_instruction() {
+    let mut chip = machine();
+    let id = chip.add_breakpoint(Breakpoint::Pc(STORE)).unwrap();
+    for n in 1..=5 {
+        let hit = hit(&mut chip);
+        assert_eq!(
+            hit,
+            BreakpointHit {
+                id,
+                breakpoint: Breakpoint::Pc(STORE),
+                pc: STORE,
+                addr: None,
+            }
+        );
+        assert_eq!(chip.pc(), STORE);
+        assert_eq!(chip.v()[0], n);
+        // not stored yet
+        assert_eq!(chip.memory()[DATA as usize + 2], n - 1);
+    }
+    assert!(chip.remove_breakpoint(id));
+    assert!(!chip.remove_breakpoint(id));
+    assert_eq!(chip.run(1000), Ok(StepOutcome::WaitingForKey));
+}
+
+#[test]
+fn conditions() {
+    let mut chip = machine();
+    chip.add_breakpoint(Breakpoint::PcIf(STORE, v0(Comparison::Ge, 4)))
+        .unwrap();
+    assert_eq!(hit(&mut chip).pc, STORE);
+    assert_eq!(chip.v()[0], 4);
+
+    // fires after the instruction that makes it true, only once
+    let mut chip = machine();
+    chip.add_breakpoint(Breakpoint::Condition(v0(Comparison::Eq, 3)))
+        .unwrap();
+    let hit = hit(&mut chip);
+    assert_eq!(hit.pc, LOOP);
+    assert_eq!(chip.pc(), STORE);
+    assert_eq!(chip.v()[0], 3);
+    assert_eq!(chip.run(1000), Ok(StepOutcome::WaitingForKey));
+}
+
+#[test]
+fn memory_watchpoints() {
+    let mut chip = machine();
+    // nothing reads data
+    chip.add_breakpoint(Breakpoint::Memory {
+        start: DATA,
+        end: DATA + 2,
+        access: Access::Read,
+    })
+    .unwrap();
+    let id = chip
+        .add_breakpoint(Breakpoint::Memory {
+            start: DATA + 2,
+            end: DATA + 10,
+            access: Access::ReadWrite,
+        })
+        .unwrap();
+    let hit = hit(&mut chip);
+    assert_eq!(hit.id, id);
+    assert_eq!(hit.pc, STORE);
+    // the store starts before the range, the first byte inside it is reported
+    assert_eq!(hit.addr, Some(DATA + 2));
+    // after the store
+    assert_eq!(chip.pc(), STORE + 2);
+    assert_eq!(chip.memory()[DATA as usize + 2], 1);
+}
+
+#[test]
+fn watchpoints_report_the_address() {
+    // a range that starts before the store
+    let mut chip = machine();
+    chip.add_breakpoint(Breakpoint::Memory {
+        start: 0x200,
+        end: DATA + 10,
+        access: Access::Write,
+    })
+    .unwrap();
+    assert_eq!(hit(&mut chip).addr, Some(DATA));
+}
+
+#[test]
+fn i_watchpoints() {
+    let mut chip = machine();
+    chip.add_breakpoint(Breakpoint::I(Access::Write)).unwrap();
+    assert_eq!(hit(&mut chip).pc, 0x202);
+    assert_eq!(chip.i(), DATA);
+
+    chip.clear_breakpoints();
+    chip.add_breakpoint(Breakpoint::I(Access::Read)).unwrap();
+    assert_eq!(hit(&mut chip).pc, STORE);
+}
+
+#[test]
+fn opcode_classes() {
+    let mut chip = machine();
+    chip.add_breakpoint(Breakpoint::Opcode {
+        mask: 0xF0FF,
+        value: 0xF00A,
+    })
+    .unwrap();
+    assert_eq!(hit(&mut chip).pc, WAIT);
+    assert_eq!(chip.pc(), WAIT);
+    // waiting for the key doesn't break again every tick
+    assert_eq!(chip.run(1000), Ok(StepOutcome::WaitingForKey));
+    assert_eq!(chip.run(1000), Ok(StepOutcome::WaitingForKey));
+}
+
+#[test]
+fn slots() {
+    let mut chip = machine();
+    for addr in 0..MAX_BREAKPOINTS as u16 {
+        assert_eq!(
+            chip.add_breakpoint(Breakpoint::Pc(addr)),
+            Some(addr as usize)
+        );
+    }
+    assert_eq!(chip.add_breakpoint(Breakpoint::Pc(0x300)), None);
+    assert!(chip.remove_breakpoint(3));
+    assert_eq!(chip.breakpoints().count(), MAX_BREAKPOINTS - 1);
+    assert_eq!(chip.add_breakpoint(Breakpoint::Pc(0x300)), Some(3));
+    assert_eq!(chip.breakpoints().nth(3), Some((3, Breakpoint::Pc(0x300))));
+}
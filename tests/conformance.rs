@@ -0,0 +1,199 @@
+// Runs test ROMs through chip8-headless for a fixed number of frames and
+// compares the screen with the golden images in tests/golden, one per ROM and
+// quirks preset
+//
+// The goldens are what `chip8-headless --ascii` writes, running the binary
+// itself keeps the frame loop and the dump the same as in CI. After a change
+// that is supposed to alter a screen regenerate them with
+//     CHIP8_BLESS=1 cargo test --test conformance
+// and check the diff
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::process::Command;
+
+const FRAMES: &str = "1000";
+const SPEED: &str = "10";
+
+fn path(file: &str) -> String {
+    format!("{}/{}", env!("CARGO_MANIFEST_DIR"), file)
+}
+
+// The screen after running rom_path with the preset's quirks
+fn run(name: &str, rom_path: &str, preset: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8-headless"))
+        .args(["--quirks", preset, "--frames", FRAMES, "--speed", SPEED])
+        .args(["--ascii", "-", rom_path])
+        .output()
+        .expect("couldn't run chip8-headless");
+    assert!(
+        output.status.success(),
+        "{} with the {} quirks failed: {}",
+        name,
+        preset,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// A ROM built by the test goes through a file like the ones shipped
+fn write_rom(name: &str, preset: &str, rom: &[u8]) -> String {
+    let path = env::temp_dir().join(format!(
+        "chip8-conformance-{}-{}-{}.ch8",
+        name,
+        preset,
+        std::process::id()
+    ));
+    fs::write(&path, rom).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn check_file(name: &str, rom_path: &str, preset: &str) {
+    let screen = run(name, rom_path, preset);
+    let golden_path = path(&format!("tests/golden/{}.{}.txt", name, preset));
+
+    if env::var_os("CHIP8_BLESS").is_some() {
+        fs::write(&golden_path, &screen).unwrap();
+        return;
+    }
+
+    let mut golden = String::new();
+    File::open(&golden_path)
+        .and_then(|mut f| f.read_to_string(&mut golden))
+        .unwrap_or_else(|err| panic!("couldn't read {}: {}", golden_path, err));
+    assert!(
+        screen == golden,
+        "{} with the {} quirks doesn't match {}, got:\n{}",
+        name,
+        preset,
+        golden_path,
+        screen
+    );
+}
+
+fn check(name: &str, rom: &[u8], preset: &str) {
+    let rom_path = write_rom(name, preset, rom);
+    check_file(name, &rom_path, preset);
+    fs::remove_file(rom_path).unwrap();
+}
+
+// Assemble a ROM from opcodes, `routines` are placed at 0x300
+fn assemble(main: &[u16], routines: &[u16]) -> Vec<u8> {
+    let mut rom = vec![0; 0x100 + routines.len() * 2];
+    for (n, opcode) in main.iter().enumerate() {
+        rom[n * 2..n * 2 + 2].copy_from_slice(&opcode.to_be_bytes());
+    }
+    for (n, opcode) in routines.iter().enumerate() {
+        rom[0x100 + n * 2..0x100 + n * 2 + 2].copy_from_slice(&opcode.to_be_bytes());
+    }
+    rom
+}
+
+// Prints V0 in decimal at (V8, V9) and moves V8 to the next column,
+// clobbers V0-V2 and I
+const PRINT: [u16; 13] = [
+    0xA380, // LD I, 0x380
+    0xF033, // LD B, V0
+    0xF265, // LD V0 - V2, [I]
+    0xF029, // LD F, V0
+    0xD895, // DRW V8, V9, 5
+    0x7805, // ADD V8, 5
+    0xF129, // LD F, V1
+    0xD895, // DRW V8, V9, 5
+    0x7805, // ADD V8, 5
+    0xF229, // LD F, V2
+    0xD895, // DRW V8, V9, 5
+    0x7807, // ADD V8, 7
+    0x00EE, // RET
+];
+
+// A screen line with the VC and VF left by `ops`
+fn flags_line(ops: &[u16]) -> Vec<u16> {
+    let mut line = ops.to_vec();
+    line.extend_from_slice(&[
+        0x8AC0, // LD VA, VC
+        0x8BF0, // LD VB, VF
+        0x80A0, // LD V0, VA
+        0x2300, // CALL PRINT
+        0x80B0, // LD V0, VB
+        0x2300, // CALL PRINT
+        0x6800, // LD V8, 0
+        0x7906, // ADD V9, 6
+    ]);
+    line
+}
+
+// The arithmetic and shift results and their VF, one instruction per line:
+// 8XY4, 8XY5, 8XY7, 8XY6 and 8XYE
+fn flags_rom() -> Vec<u8> {
+    let mut main = vec![0x6800, 0x6901]; // V8 = 0, V9 = 1
+    main.extend(flags_line(&[0x6CF0, 0x6D20, 0x8CD4])); // 0xF0 + 0x20
+    main.extend(flags_line(&[0x6C10, 0x6D20, 0x8CD5])); // 0x10 - 0x20
+    main.extend(flags_line(&[0x6C10, 0x6D20, 0x8CD7])); // 0x20 - 0x10
+    main.extend(flags_line(&[0x6C03, 0x6D81, 0x8CD6])); // 0x03 or 0x81 >> 1
+    main.extend(flags_line(&[0x6C03, 0x6D81, 0x8CDE])); // 0x03 or 0x81 << 1
+    let end = 0x200 + main.len() as u16 * 2;
+    main.push(0x1000 | end); // JP to itself
+    assemble(&main, &PRINT)
+}
+
+// One digit per quirk, left to right. What each of them shows with the quirk
+// off / on:
+//   vf_reset      VF after 8XY1 with VF = 5: 5 / 0
+//   shift         8XY6 with VX = 1 and VY = 2: 1 / 0
+//   load_store    FX65 right after FX55 of 7 and 8: 0 (increment),
+//                 8 (increment_x) or 7 (unchanged)
+//   jump          BNNN landing on a LD V0, 1 or a LD V0, 2 4 bytes on: 1 / 2
+//   clip          VF drawing where a sprite past the right edge would wrap: 1 / 0
+//   display_wait  what's left of DT = 5 after 4 sprites: 5 or 4 / 1
+fn quirks_rom() -> Vec<u8> {
+    let mut main = vec![
+        0x6802, 0x6902, // V8 = 2, V9 = 2
+        0x6F05, 0x6000, 0x6100, 0x8011, 0x80F0, 0x2300, // vf_reset
+        0x6001, 0x6102, 0x8016, 0x2300, // shift
+        0x6007, 0x6108, 0xA3C0, 0xF155, 0xF065, 0x2300, // load_store
+        0x6000, 0x6204, // jump
+    ];
+    let target = 0x200 + (main.len() as u16 + 1) * 2;
+    main.extend_from_slice(&[
+        0xB000 | target,
+        0x6001,
+        0x1000 | (target + 6),
+        0x6002,
+        0x2300,
+    ]);
+    main.extend_from_slice(&[
+        0xA308, 0x6C3C, 0x6D1F, 0xDCD1, 0xA309, 0x6C00, 0xDCD1, 0x80F0, 0x2300, // clip
+        0x6005, 0xF015, 0xA309, 0x6C3F, 0x6D00, 0xDCD1, 0xDCD1, 0xDCD1, 0xDCD1, 0xF007,
+        0x2300, // display_wait
+    ]);
+    let end = 0x200 + main.len() as u16 * 2;
+    main.push(0x1000 | end); // JP to itself
+
+    // prints the digit in V0 at (V8, V9) and moves V8 on, then the sprites
+    let routines = [0xF029, 0xD895, 0x7806, 0x00EE, 0xFF80];
+    assemble(&main, &routines)
+}
+
+// BC_Chip8Test, prints "BON" when everything passes (see test.txt)
+// It was written for CHIP-48 style shifts and FX55/FX65 that leave I alone,
+// which only SUPER-CHIP 1.1 has. With the VIP shifts it stops at E 12 and
+// with I moving on at E 16
+#[test]
+fn bc_test() {
+    check_file("bc_test", &path("test.c8"), "schip11");
+}
+
+#[test]
+fn flags() {
+    for preset in &["vip", "chip48", "schip10", "schip11", "xochip"] {
+        check("flags", &flags_rom(), preset);
+    }
+}
+
+#[test]
+fn quirks() {
+    for preset in &["vip", "chip48", "schip10", "schip11", "xochip"] {
+        check("quirks", &quirks_rom(), preset);
+    }
+}
@@ -0,0 +1,148 @@
+extern crate chip8;
+
+use chip8::{Disassembly, Instruction, Line};
+
+// jump over a sprite, call a routine that points I at it and draws it
+const ROM: [u8; 14] = [
+    0x12, 0x04, // 200: JP 204
+    0xF0, 0x90, // 202: sprite
+    0x22, 0x08, // 204: CALL 208
+    0x12, 0x06, // 206: JP 206
+    0xA2, 0x02, // 208: LD I, 202
+    0xD0, 0x02, // 20A: DRW V0, V0, 2
+    0x00, 0xEE, // 20C: RET
+];
+
+#[test]
+fn tracing_splits_code_and_data() {
+    let disassembly = Disassembly::new(&ROM);
+    let lines = disassembly.lines();
+    assert_eq!(
+        lines[0],
+        Line::Code {
+            addr: 0x200,
+            instruction: Instruction::Jp { nnn: 0x204 },
+        }
+    );
+    assert_eq!(
+        lines[1],
+        Line::Data {
+            addr: 0x202,
+            byte: 0xF0,
+        }
+    );
+    assert_eq!(
+        lines[2],
+        Line::Data {
+            addr: 0x203,
+            byte: 0x90,
+        }
+    );
+    assert_eq!(lines.len(), 8);
+    assert!(lines[3..].iter().all(|line| match *line {
+        Line::Code { .. } => true,
+        Line::Data { .. } => false,
+    }));
+}
+
+#[test]
+fn linear_decodes_everything() {
+    let disassembly = Disassembly::linear(&ROM);
+    // the sprite isn't an instruction so it's still data
+    assert_eq!(disassembly.lines().len(), 8);
+    assert_eq!(
+        disassembly.lines()[1],
+        Line::Data {
+            addr: 0x202,
+            byte: 0xF0,
+        }
+    );
+    assert_eq!(disassembly.label(0x208), None);
+}
+
+#[test]
+fn labels() {
+    let disassembly = Disassembly::new(&ROM);
+    assert_eq!(disassembly.label(0x202), Some("data_202".to_string()));
+    assert_eq!(disassembly.label(0x204), Some("label_204".to_string()));
+    assert_eq!(disassembly.label(0x208), Some("sub_208".to_string()));
+    assert_eq!(disassembly.label(0x20A), None);
+}
+
+#[test]
+fn octo_syntax() {
+    let octo = Disassembly::new(&ROM).octo();
+    let code: Vec<&str> = octo
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .collect();
+    assert_eq!(
+        code,
+        [
+            "jump label_204",
+            ": data_202",
+            "0xf0",
+            "0x90",
+            ": label_204",
+            "sub_208",
+            ": label_206",
+            "jump label_206",
+            ": sub_208",
+            "i := data_202",
+            "sprite v0 v0 2",
+            "return",
+        ]
+    );
+}
+
+#[test]
+fn classic_syntax() {
+    let classic = Disassembly::new(&ROM).classic();
+    let code: Vec<&str> = classic
+        .lines()
+        .map(|line| line.split(';').next().unwrap().trim())
+        .collect();
+    assert_eq!(
+        code,
+        [
+            "JP label_204",
+            "data_202:",
+            "DB 0xF0",
+            "DB 0x90",
+            "label_204:",
+            "CALL sub_208",
+            "label_206:",
+            "JP label_206",
+            "sub_208:",
+            "LD I, data_202",
+            "DRW V0, V0, 2",
+            "RET",
+        ]
+    );
+}
+
+// Every instruction, with a byte left over
+const EVERY_INSTRUCTION: [u16; 52] = [
+    0x0123, 0x00C1, 0x00D2, 0x00E0, 0x00EE, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF, 0x1234, 0x2345,
+    0x3A12, 0x4B34, 0x5120, 0x5232, 0x5343, 0x6C56, 0x7D78, 0x8120, 0x8121, 0x8122, 0x8123, 0x8124,
+    0x8125, 0x8126, 0x8127, 0x812E, 0x9340, 0xA456, 0xB567, 0xC689, 0xD125, 0xE19E, 0xE2A1, 0xF000,
+    0x1234, 0xF201, 0xF002, 0xF307, 0xF40A, 0xF515, 0xF618, 0xF71E, 0xF829, 0xF930, 0xFA33, 0xFB3A,
+    0xFC55, 0xFD65, 0xFE75, 0xFF85,
+];
+
+#[test]
+fn classic_assembles_back() {
+    let mut every: Vec<u8> = EVERY_INSTRUCTION
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .collect();
+    every.push(0xAB);
+    for (rom, disassembly) in [
+        (&ROM[..], Disassembly::new(&ROM)),
+        (&every[..], Disassembly::linear(&every)),
+    ] {
+        let source = disassembly.classic();
+        let assembly = chip8::assemble(&source).unwrap_or_else(|err| panic!("{}\n{}", err, source));
+        assert_eq!(assembly.rom, rom, "{}", source);
+    }
+}
@@ -0,0 +1,173 @@
+extern crate chip8;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+
+use chip8::Cpu;
+
+// V0 counts up and gets stored at data forever
+const PROGRAM: &str = "
+        LD V0, 0
+loop:   LD I, data
+        ADD V0, 1
+        LD [I], V0
+        JP loop
+data:   DB 0
+";
+
+// The gdb end of the connection
+struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    fn send(&mut self, data: &str) {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", data, sum).unwrap();
+    }
+
+    // The next packet, acked
+    fn receive(&mut self) -> String {
+        let mut skipped = Vec::new();
+        self.reader.read_until(b'$', &mut skipped).unwrap();
+        let mut packet = Vec::new();
+        self.reader.read_until(b'#', &mut packet).unwrap();
+        packet.pop();
+        let mut checksum = [0; 2];
+        self.reader.read_exact(&mut checksum).unwrap();
+        let sum = packet.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        assert_eq!(
+            u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16),
+            Ok(sum)
+        );
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(packet).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.receive()
+    }
+}
+
+fn start() -> (Client, JoinHandle<Cpu>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut chip = chip8::new(chip8::Quirks::default());
+        chip.load_rom(&chip8::assemble(PROGRAM).unwrap().rom)
+            .unwrap();
+        chip8::serve_gdb(&mut chip, stream, 10).unwrap();
+        chip
+    });
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let reader = BufReader::new(stream.try_clone().unwrap());
+    (Client { stream, reader }, server)
+}
+
+#[test]
+fn registers_and_memory() {
+    let (mut gdb, server) = start();
+    assert!(gdb
+        .request("qSupported:multiprocess+;swbreak+")
+        .contains("qXfer:features:read+"));
+    let xml = gdb.request("qXfer:features:read:target.xml:0,1000");
+    assert!(xml.starts_with('l'));
+    assert!(xml.contains(r#"<reg name="pc" bitsize="16" type="code_ptr"/>"#));
+    assert!(gdb
+        .request("qXfer:features:read:target.xml:0,10")
+        .starts_with('m'));
+    assert_eq!(gdb.request("?"), "S05");
+
+    // v0-vf, i, pc, sp, dt, st
+    assert_eq!(
+        gdb.request("g"),
+        format!("{}{}{}", "00".repeat(16), "00000200", "000000")
+    );
+    assert_eq!(gdb.request("p11"), "0200");
+    assert_eq!(gdb.request("m200,4"), "6000a20a");
+    assert_eq!(gdb.request("M300,2:abcd"), "OK");
+    assert_eq!(gdb.request("m300,2"), "abcd");
+    assert_eq!(gdb.request("mffff,4"), "00");
+    assert_eq!(gdb.request("m10000,1"), "E01");
+    assert_eq!(gdb.request("P10=0300"), "OK");
+    assert_eq!(gdb.request("P3=7f"), "OK");
+    assert_eq!(gdb.request("P15=00"), "E01");
+    assert_eq!(gdb.request("vMustReplyEmpty"), "");
+
+    assert_eq!(gdb.request("D"), "OK");
+    let chip = server.join().unwrap();
+    assert_eq!(chip.i(), 0x300);
+    assert_eq!(chip.v()[3], 0x7F);
+    assert_eq!(&chip.memory()[0x300..0x302], &[0xAB, 0xCD]);
+}
+
+#[test]
+fn breakpoints_step_and_continue() {
+    let (mut gdb, server) = start();
+    assert_eq!(gdb.request("QStartNoAckMode"), "OK");
+
+    assert_eq!(gdb.request("Z0,206,2"), "OK");
+    for n in 1..=3 {
+        assert_eq!(gdb.request("c"), "S05");
+        assert_eq!(gdb.request("p11"), "0206");
+        assert_eq!(gdb.request("p0"), format!("{:02x}", n));
+    }
+    // stepping runs the instruction under the breakpoint
+    assert_eq!(gdb.request("s"), "S05");
+    assert_eq!(gdb.request("p11"), "0208");
+    assert_eq!(gdb.request("z0,206,2"), "OK");
+    assert_eq!(gdb.request("z0,206,2"), "E01");
+
+    // a write watchpoint on data stops after the store
+    assert_eq!(gdb.request("Z2,20a,1"), "OK");
+    assert_eq!(gdb.request("c"), "T05watch:20a;");
+    assert_eq!(gdb.request("p11"), "0208");
+    assert_eq!(gdb.request("m20a,1"), "04");
+    assert_eq!(gdb.request("z2,20a,1"), "OK");
+    // a wider one reports the address written, not where it starts
+    assert_eq!(gdb.request("Z2,200,10"), "OK");
+    assert_eq!(gdb.request("c"), "T05watch:20a;");
+    assert_eq!(gdb.request("z2,200,10"), "OK");
+
+    // continuing from an address
+    assert_eq!(gdb.request("Z1,204,2"), "OK");
+    assert_eq!(gdb.request("c200"), "S05");
+    assert_eq!(gdb.request("p0"), "00");
+
+    gdb.send("k");
+    let chip = server.join().unwrap();
+    assert_eq!(chip.pc(), 0x204);
+}
+
+#[test]
+fn interrupts_and_errors() {
+    let (mut gdb, server) = start();
+    gdb.send("c");
+    gdb.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(gdb.receive(), "S02");
+    assert_eq!(gdb.request("?"), "S02");
+
+    assert_eq!(gdb.request("M300,2:ffff"), "OK");
+    gdb.send("c300");
+    // the error goes to the console as hex
+    let message = gdb.receive();
+    assert!(message.starts_with('O'));
+    let text: Vec<u8> = (1..message.len())
+        .step_by(2)
+        .map(|n| u8::from_str_radix(&message[n..n + 2], 16).unwrap())
+        .collect();
+    assert_eq!(
+        String::from_utf8(text).unwrap(),
+        "unknown opcode FFFF at 300\n"
+    );
+    assert_eq!(gdb.receive(), "S04");
+    assert_eq!(gdb.request("p11"), "0300");
+
+    drop(gdb);
+    server.join().unwrap();
+}
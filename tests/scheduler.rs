@@ -0,0 +1,117 @@
+extern crate chip8;
+
+use std::time::Duration;
+
+use chip8::{Quirks, Scheduler, Speed, StepOutcome};
+
+#[test]
+fn sixty_frames_a_second() {
+    let mut scheduler = Scheduler::new(Speed::PerFrame(10));
+    // 16ms steps would lose 2/3 of a millisecond a frame with whole millis
+    let mut frames = 0;
+    for _ in 0..1000 {
+        frames += scheduler.advance(Duration::from_millis(16));
+    }
+    assert_eq!(frames, 960);
+    // uneven steps add up the same
+    let mut scheduler = Scheduler::new(Speed::PerFrame(10));
+    let mut frames = 0;
+    for n in 0..3000 {
+        frames += scheduler.advance(Duration::from_micros(1000 + n % 7 * 1000));
+    }
+    let elapsed: u64 = (0..3000).map(|n| 1000 + n % 7 * 1000).sum();
+    assert_eq!(u64::from(frames), elapsed * 60 / 1_000_000);
+}
+
+#[test]
+fn until_next_frame() {
+    let mut scheduler = Scheduler::new(Speed::PerFrame(10));
+    assert_eq!(scheduler.advance(Duration::from_millis(10)), 0);
+    let left = scheduler.until_next_frame();
+    assert_eq!(left, Duration::from_nanos(6_666_667));
+    assert_eq!(scheduler.advance(left - Duration::from_nanos(1)), 0);
+    assert_eq!(scheduler.advance(Duration::from_nanos(1)), 1);
+    // far behind it catches up a few frames, not all of them
+    assert_eq!(scheduler.advance(Duration::from_secs(3600)), 4);
+}
+
+#[test]
+fn instructions() {
+    let mut scheduler = Scheduler::new(Speed::PerFrame(15));
+    assert_eq!(scheduler.instructions(), 15);
+    // 700 a second is 11.67 a frame, exactly 700 over 60 frames
+    scheduler.speed = Speed::Hz(700);
+    let per_frame: Vec<u32> = (0..60).map(|_| scheduler.instructions()).collect();
+    assert_eq!(per_frame.iter().sum::<u32>(), 700);
+    assert!(per_frame.iter().all(|&n| n == 11 || n == 12));
+}
+
+#[test]
+fn speeds() {
+    assert_eq!(Speed::parse("15"), Some(Speed::PerFrame(15)));
+    assert_eq!(Speed::parse("700hz"), Some(Speed::Hz(700)));
+    assert_eq!(Speed::parse(" 1000 Hz"), Some(Speed::Hz(1000)));
+    assert_eq!(Speed::parse("0"), None);
+    assert_eq!(Speed::parse("fast"), None);
+    assert_eq!(Speed::parse("hz"), None);
+
+    assert_eq!(Speed::PerFrame(10).faster(), Speed::PerFrame(15));
+    assert_eq!(Speed::PerFrame(10).slower(), Speed::PerFrame(7));
+    assert_eq!(Speed::Hz(700).faster(), Speed::PerFrame(15));
+    assert_eq!(Speed::Hz(700).slower(), Speed::PerFrame(10));
+    assert_eq!(Speed::PerFrame(1).slower(), Speed::PerFrame(1));
+    assert_eq!(Speed::Hz(1_000_000).faster(), Speed::Hz(1_000_000));
+    assert_eq!(Speed::Hz(700).to_string(), "700 instructions per second");
+}
+
+// Sprites drawn in a second by a loop that only draws, frames run the way
+// the frontends run them
+fn draws_per_second(quirks: Quirks) -> u32 {
+    let mut chip = chip8::new(quirks);
+    // DRW V0, V0, 1 and JP back
+    chip.load_rom(&[0xD0, 0x01, 0x12, 0x00]).unwrap();
+    let mut scheduler = Scheduler::new(Speed::PerFrame(1000));
+    let mut draws = 0;
+    for _ in 0..60 {
+        scheduler.start_frame(chip.cycles());
+        while scheduler.more(chip.cycles()) {
+            match chip.cpu_tick() {
+                Ok(StepOutcome::WaitingForVblank) => break,
+                Ok(_) if chip.draw_flag => {
+                    draws += 1;
+                    chip.draw_flag = false;
+                }
+                outcome => assert_eq!(outcome, Ok(StepOutcome::Executed)),
+            }
+        }
+        chip.timers_tick();
+    }
+    draws
+}
+
+#[test]
+fn display_wait() {
+    assert_eq!(draws_per_second(Quirks::COSMAC_VIP), 60);
+    assert_eq!(draws_per_second(Quirks::CHIP_48), 30_000);
+}
+
+#[test]
+fn draws_last_the_frame() {
+    let mut chip = chip8::new(Quirks::CHIP_48);
+    // DRW V0, V0, 1 and three instructions that don't draw
+    chip.load_rom(&[0xD0, 0x01, 0x60, 0x01, 0x70, 0x01, 0x12, 0x06])
+        .unwrap();
+    let mut scheduler = Scheduler::new(Speed::PerFrame(4));
+    scheduler.start_frame(chip.cycles());
+    while scheduler.more(chip.cycles()) {
+        assert_eq!(chip.cpu_tick(), Ok(StepOutcome::Executed));
+    }
+    assert!(chip.draw_flag);
+    // once the frontend has shown it, only another draw sets it again
+    chip.draw_flag = false;
+    scheduler.start_frame(chip.cycles());
+    while scheduler.more(chip.cycles()) {
+        chip.cpu_tick().unwrap();
+    }
+    assert!(!chip.draw_flag);
+}
@@ -0,0 +1,119 @@
+extern crate chip8;
+
+use chip8::{Cpu, Quirks, Scheduler, Speed, StepOutcome, VIP_FRAME_CYCLES, VIP_INTERRUPT_CYCLES};
+
+const BUDGET: u64 = (VIP_FRAME_CYCLES - VIP_INTERRUPT_CYCLES) as u64;
+
+fn machine(program: &[u16]) -> Cpu {
+    let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+    let mut chip = chip8::new(Quirks::COSMAC_VIP);
+    chip.load_rom(&rom).unwrap();
+    chip
+}
+
+// Machine cycles the next instruction takes
+fn cost(chip: &mut Cpu) -> u64 {
+    let before = chip.cycles();
+    assert_eq!(chip.cpu_tick(), Ok(StepOutcome::Executed));
+    chip.timers_tick();
+    chip.cycles() - before
+}
+
+#[test]
+fn costs() {
+    let mut chip = machine(&[
+        0x6005, // LD V0, 5
+        0x3005, // SE V0, 5
+        0x0000, //
+        0x3006, // SE V0, 6
+        0xA300, // LD I, 0x300
+        0x6100, // LD V1, 0
+        0xD111, // DRW V1, V1, 1
+        0x6103, // LD V1, 3
+        0xD111, // DRW V1, V1, 1
+        0xF055, // LD [I], V0
+        0xF255, // LD [I], V2
+    ]);
+    assert_eq!(cost(&mut chip), 26);
+    // skipping takes longer
+    assert_eq!(cost(&mut chip), 34);
+    assert_eq!(cost(&mut chip), 30);
+    assert_eq!(cost(&mut chip), 32);
+    assert_eq!(cost(&mut chip), 26);
+    // a sprite off a byte boundary is shifted into place bit by bit
+    let aligned = cost(&mut chip);
+    cost(&mut chip);
+    let unaligned = cost(&mut chip);
+    assert_eq!(aligned, 64);
+    assert_eq!(unaligned, 104);
+    // a register more is a store more
+    let one = cost(&mut chip);
+    let three = cost(&mut chip);
+    assert_eq!(three - one, 28);
+}
+
+#[test]
+fn breakpoints_cost_nothing() {
+    let mut chip = machine(&[0x6005]);
+    chip.add_breakpoint(chip8::Breakpoint::Pc(0x200)).unwrap();
+    match chip.cpu_tick() {
+        Ok(StepOutcome::Break(_)) => {}
+        outcome => panic!("expected a breakpoint, got {:?}", outcome),
+    }
+    assert_eq!(chip.cycles(), 0);
+    assert_eq!(cost(&mut chip), 26);
+}
+
+// Runs a frame the way the frontends do, returns the instructions it ran
+fn frame(scheduler: &mut Scheduler, chip: &mut Cpu) -> u32 {
+    let mut instructions = 0;
+    scheduler.start_frame(chip.cycles());
+    while scheduler.more(chip.cycles()) {
+        let outcome = chip.cpu_tick().unwrap();
+        instructions += 1;
+        if outcome == StepOutcome::WaitingForVblank {
+            break;
+        }
+    }
+    chip.timers_tick();
+    instructions
+}
+
+#[test]
+fn vip_frames() {
+    // ADD V0, 1 and JP back, 30 + 32 cycles
+    let mut chip = machine(&[0x7001, 0x1200]);
+    let mut scheduler = Scheduler::new(Speed::CosmacVip);
+    let instructions: u32 = (0..60).map(|_| frame(&mut scheduler, &mut chip)).sum();
+    // what runs past the end of a frame comes off the next one
+    assert!(chip.cycles() >= 60 * BUDGET);
+    assert!(chip.cycles() < 60 * BUDGET + 32);
+    // 1777 loops of 62 cycles are the first to make 60 frames
+    assert_eq!(instructions, 1777 * 2);
+}
+
+#[test]
+fn vip_frames_end_at_the_interrupt() {
+    // a sprite, then a loop
+    let mut chip = machine(&[0xD001, 0x1202]);
+    let mut scheduler = Scheduler::new(Speed::CosmacVip);
+    assert_eq!(frame(&mut scheduler, &mut chip), 2);
+    let drawn = chip.cycles();
+    // the next frame gets all of its cycles, none of the ones left waiting
+    frame(&mut scheduler, &mut chip);
+    assert_eq!((chip.cycles() - drawn).div_ceil(32), BUDGET.div_ceil(32));
+
+    // a change of speed starts afresh too
+    scheduler.speed = Speed::PerFrame(3);
+    assert_eq!(frame(&mut scheduler, &mut chip), 3);
+    scheduler.speed = Speed::CosmacVip;
+    assert_eq!(frame(&mut scheduler, &mut chip), BUDGET.div_ceil(32) as u32);
+}
+
+#[test]
+fn speeds() {
+    assert_eq!(Speed::parse("VIP"), Some(Speed::CosmacVip));
+    assert_eq!(Speed::CosmacVip.to_string(), "COSMAC VIP speed");
+    assert_eq!(Speed::CosmacVip.faster(), Speed::PerFrame(15));
+    assert_eq!(Speed::CosmacVip.slower(), Speed::PerFrame(7));
+}
@@ -0,0 +1,141 @@
+extern crate chip8;
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use chip8::{Breakpoint, Cpu, Quirks, StepOutcome, TraceRecord};
+
+// A trace that stays readable after the Cpu takes it
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Shared {
+    fn lines(&self) -> Vec<String> {
+        let text = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+}
+
+fn machine(quirks: Quirks, source: &str) -> (Cpu, Shared) {
+    let mut chip = chip8::new(quirks);
+    chip.load_rom(&chip8::assemble(source).unwrap().rom)
+        .unwrap();
+    let trace = Shared::default();
+    chip.start_trace(trace.clone());
+    (chip, trace)
+}
+
+fn run(chip: &mut Cpu, cycles: usize) {
+    for _ in 0..cycles {
+        chip.cpu_tick().unwrap();
+    }
+}
+
+#[test]
+fn records() {
+    let (mut chip, trace) = machine(
+        Quirks::default(),
+        "
+        LD V0, 42
+        LD I, data
+        LD B, V0
+        LD DT, V0
+end:    JP end
+data:
+",
+    );
+    run(&mut chip, 5);
+    chip.stop_trace().unwrap();
+    assert!(!chip.tracing());
+    run(&mut chip, 5);
+
+    let lines = trace.lines();
+    let fields: Vec<&str> = lines
+        .iter()
+        .map(|line| line.split(" ; ").next().unwrap())
+        .collect();
+    assert_eq!(
+        fields,
+        [
+            "0 0200 602A I=0000 V0=2A",
+            "1 0202 A20A I=020A",
+            "2 0204 F033 I=020A [020A]=00 [020B]=04 [020C]=02",
+            "3 0206 F015 I=020A DT=2A",
+            "4 0208 1208 I=020A",
+        ]
+    );
+    for line in &lines {
+        let record = TraceRecord::parse(line).unwrap();
+        assert_eq!(&record.to_string(), line);
+    }
+    let record = TraceRecord::parse(&lines[2]).unwrap();
+    assert_eq!(
+        record.mnemonic,
+        chip8::Instruction::LdBVx { x: 0 }.to_string()
+    );
+    assert_eq!(record.writes, [(0x20A, 0), (0x20B, 4), (0x20C, 2)]);
+}
+
+#[test]
+fn long_opcodes_and_breakpoints() {
+    let (mut chip, trace) = machine(
+        Quirks::XO_CHIP,
+        "
+        LD I, LONG 0x1234
+end:    JP end
+",
+    );
+    chip.add_breakpoint(Breakpoint::Pc(0x200)).unwrap();
+    match chip.cpu_tick() {
+        Ok(StepOutcome::Break(_)) => {}
+        outcome => panic!("expected a breakpoint, got {:?}", outcome),
+    }
+    // nothing ran yet
+    assert!(trace.lines().is_empty());
+    run(&mut chip, 1);
+    let lines = trace.lines();
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].starts_with("0 0200 F0001234 I=1234 ; "));
+    assert_eq!(TraceRecord::parse(&lines[0]).unwrap().opcode, 0xF000_1234);
+}
+
+#[test]
+fn differences() {
+    let source = "
+        LD V1, 8
+        LD V2, 2
+        SHR V1, V2
+end:    JP end
+";
+    let mut quirks = Quirks::default();
+    quirks.set("shift", true);
+    let (mut a, trace_a) = machine(quirks, source);
+    quirks.set("shift", false);
+    let (mut b, trace_b) = machine(quirks, source);
+    run(&mut a, 4);
+    run(&mut b, 4);
+
+    let records = |trace: &Shared| -> Vec<TraceRecord> {
+        trace
+            .lines()
+            .iter()
+            .map(|line| TraceRecord::parse(line).unwrap())
+            .collect()
+    };
+    let (a, b) = (records(&trace_a), records(&trace_b));
+    assert!(a[1].differences(&b[1]).is_empty());
+    assert_eq!(a[2].differences(&b[2]), ["V1: 04 vs 01"]);
+
+    assert_eq!(TraceRecord::parse("not a trace"), None);
+    assert_eq!(TraceRecord::parse("0 0200 00E0 I=0000 X=1 ; CLS"), None);
+}